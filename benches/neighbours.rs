@@ -0,0 +1,115 @@
+//! Measure how many neighbours are generated and scored per second, from a sample of positions
+//! reachable from the solved one.
+//!
+//! Run with `cargo bench --bench neighbours`
+
+use bachar_cube::format_big_int;
+use bachar_cube::position::{NeighboursStack, Position};
+use std::ops::ControlFlow;
+use std::time::Instant;
+
+const NUM_POSITIONS: usize = 2_000;
+
+const NUM_ROUNDS: usize = 100;
+
+fn main() {
+    // A fixed walk, to have a variety of shapes
+    let mut stack = NeighboursStack::new();
+    let mut positions = vec![];
+    let mut position = Position::solved();
+    let mut seed = 0x2545_F491_4F6C_DD1D_u64;
+    while positions.len() < NUM_POSITIONS {
+        position.neighbours(&mut stack);
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        position = stack.neighbours()[seed as usize % stack.neighbours().len()].position();
+        positions.push(position);
+    }
+
+    let start = Instant::now();
+    let mut num_neighbours = 0;
+    for _ in 0..NUM_ROUNDS {
+        for position in &positions {
+            position.neighbours(&mut stack);
+            num_neighbours += stack.neighbours().len();
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{} expansions, {} neighbours in {:.2?}: {:.1}k expansions/s, {:.1}M neighbours/s",
+        format_big_int(NUM_POSITIONS * NUM_ROUNDS),
+        format_big_int(num_neighbours),
+        elapsed,
+        (NUM_POSITIONS * NUM_ROUNDS) as f64 / elapsed.as_secs_f64() / 1e3,
+        num_neighbours as f64 / elapsed.as_secs_f64() / 1e6
+    );
+
+    let start = Instant::now();
+    let mut num_visited = 0;
+    for _ in 0..NUM_ROUNDS {
+        for position in &positions {
+            let _ = position.visit_neighbours(&mut stack, true, |_| {
+                num_visited += 1;
+                ControlFlow::Continue(())
+            });
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "Streamed without redundant changes, {} neighbours in {:.2?}: {:.1}k expansions/s",
+        format_big_int(num_visited),
+        elapsed,
+        (NUM_POSITIONS * NUM_ROUNDS) as f64 / elapsed.as_secs_f64() / 1e3,
+    );
+
+    let start = Instant::now();
+    let mut total_score = 0;
+    for _ in 0..NUM_ROUNDS {
+        for position in &positions {
+            position.neighbours(&mut stack);
+            total_score += stack
+                .neighbours()
+                .iter()
+                .map(|movement| movement.position().score() as usize)
+                .sum::<usize>();
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "With scoring (total {}) in {:.2?}: {:.1}M neighbours/s",
+        format_big_int(total_score),
+        elapsed,
+        num_neighbours as f64 / elapsed.as_secs_f64() / 1e6
+    );
+
+    let start = Instant::now();
+    let mut total_score = 0;
+    let mut neighbours = vec![];
+    let mut scores = vec![];
+    for _ in 0..NUM_ROUNDS {
+        for position in &positions {
+            position.neighbours(&mut stack);
+            neighbours.clear();
+            neighbours.extend(
+                stack
+                    .neighbours()
+                    .iter()
+                    .map(|movement| movement.position()),
+            );
+            Position::score_batch(&neighbours, &mut scores);
+            total_score += scores.iter().map(|&score| score as usize).sum::<usize>();
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "With batch scoring (total {}) in {:.2?}: {:.1}M neighbours/s",
+        format_big_int(total_score),
+        elapsed,
+        num_neighbours as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
@@ -0,0 +1,142 @@
+//! Measure the throughput and memory of [`ShardedSet`] when many threads insert at once.
+//!
+//! Run with `cargo bench --bench sharded_set`, or `cargo bench --bench sharded_set -- prefix` to
+//! measure the `PrefixSet` that it replaced instead. Set `RAYON_NUM_THREADS` to choose the number
+//! of threads.
+
+use bachar_cube::format_big_int;
+use bachar_cube::sharded_set::ShardedSet;
+use itertools::Itertools;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::time::Instant;
+
+const NUM_VALUES: u64 = 50_000_000;
+
+/// Each value is inserted this many times, like positions reached from different paths
+const REPEATS: u64 = 2;
+
+/// The set of the first version of the search: a [`HashSet`] behind a mutex for each prefix of the
+/// stirred values, without the depths
+struct PrefixSet {
+    children: Vec<Mutex<HashSet<u64>>>,
+}
+
+const PREFIX_BITS: u32 = 16;
+
+fn main() {
+    let is_prefix_set = env::args().any(|arg| arg == "prefix");
+    let memory_before = resident_bytes();
+    let sharded_set = ShardedSet::new();
+    let prefix_set = PrefixSet::new();
+
+    let start = Instant::now();
+    let inserted: usize = (0..NUM_VALUES * REPEATS)
+        .into_par_iter()
+        .map(|n| {
+            let value = xorshift(n % NUM_VALUES + 1);
+            let inserted = if is_prefix_set {
+                prefix_set.insert(value)
+            } else {
+                sharded_set.insert_if_shallower(value, (n / NUM_VALUES) as u16)
+            };
+            inserted as usize
+        })
+        .sum();
+    let elapsed = start.elapsed();
+
+    let len = if is_prefix_set {
+        prefix_set.len()
+    } else {
+        sharded_set.len()
+    };
+    assert_eq!(len as u64, NUM_VALUES);
+    let memory = resident_bytes() - memory_before;
+    println!(
+        "{} inserts ({} inserted) with {} threads in {:.2?}: {:.1}M inserts/s",
+        format_big_int((NUM_VALUES * REPEATS) as usize),
+        format_big_int(inserted),
+        rayon::current_num_threads(),
+        elapsed,
+        (NUM_VALUES * REPEATS) as f64 / elapsed.as_secs_f64() / 1e6
+    );
+    if is_prefix_set {
+        println!(
+            "{:.1} bytes per entry resident",
+            memory as f64 / NUM_VALUES as f64
+        );
+    } else {
+        println!(
+            "{:.1} bytes per entry resident ({:.1} in the tables)",
+            memory as f64 / NUM_VALUES as f64,
+            sharded_set.memory_bytes() as f64 / NUM_VALUES as f64
+        );
+    }
+}
+
+impl PrefixSet {
+    fn new() -> Self {
+        PrefixSet {
+            children: (0..2u32.pow(PREFIX_BITS))
+                .map(|_| Mutex::new(HashSet::new()))
+                .collect_vec(),
+        }
+    }
+
+    fn insert(&self, value: u64) -> bool {
+        let value = stir(value);
+        let prefix = value >> (u64::BITS - PREFIX_BITS);
+        let suffix = value ^ (prefix << (u64::BITS - PREFIX_BITS));
+        self.children[prefix as usize].lock().insert(suffix)
+    }
+
+    fn len(&self) -> usize {
+        self.children.iter().map(|child| child.lock().len()).sum()
+    }
+}
+
+/// Mix the 16-bit words of the value, in the order of their memory on little endian machines
+fn stir(v: u64) -> u64 {
+    let [mut a, mut b, mut c, mut d] = [
+        v as u16,
+        (v >> 16) as u16,
+        (v >> 32) as u16,
+        (v >> 48) as u16,
+    ];
+
+    for _ in 0..4 {
+        // Slightly inspired by the core of SHA-1
+        let new_a = (b ^ c).wrapping_add(d).wrapping_add(a.rotate_left(5));
+        let new_b = a;
+        let new_c = b.rotate_left(14);
+        let new_d = c;
+
+        a = new_a;
+        b = new_b;
+        c = new_c;
+        d = new_d;
+    }
+
+    a as u64 | (b as u64) << 16 | (c as u64) << 32 | (d as u64) << 48
+}
+
+/// Spread consecutive integers over the whole range, like the bits of positions
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+fn resident_bytes() -> usize {
+    let statm = fs::read_to_string("/proc/self/statm").unwrap_or_default();
+    let resident_pages: usize = statm
+        .split_whitespace()
+        .nth(1)
+        .and_then(|pages| pages.parse().ok())
+        .unwrap_or(0);
+    resident_pages * 4096
+}
@@ -0,0 +1,298 @@
+//! Solve many positions at once, like a list of scrambles, and summarize the results.
+//!
+//! Each line of a batch is a position in one of the formats of the crate: the pieces written by
+//! [`Position`]'s `Display`, a state of the viewer (see [`crate::viewer`]) or a sequence of
+//! changes applied to the solved position, like `T1B0T3B2, T0B3T2B1`. Empty lines and lines
+//! starting with `#` are ignored.
+
+use crate::find_solution::{SearchStats, Solution, Solver};
+use crate::position::Change;
+use crate::viewer::ViewerState;
+use crate::Position;
+use anyhow::{anyhow, Context, Result};
+use itertools::Itertools;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::fmt;
+use std::io::BufRead;
+
+/// A position of a batch, with the line where it was read
+#[derive(Debug, Clone, Copy)]
+pub struct BatchPosition {
+    pub line: usize,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub line: usize,
+    pub position: Position,
+    /// `None` if the search ended without a solution, like when it ran out of time
+    pub solution: Option<Solution>,
+    pub stats: SearchStats,
+}
+
+/// The mean, median and maximum of some values
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Summary {
+    pub mean: f64,
+    pub median: f64,
+    pub max: f64,
+}
+
+/// The aggregate of the results of a batch. The lengths are in compound movements, like
+/// [`Solution::num_movements()`], and only count the positions that were solved.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchSummary {
+    pub num_positions: usize,
+    pub num_solved: usize,
+    pub num_optimal: usize,
+    pub length: Option<Summary>,
+    pub seconds: Option<Summary>,
+}
+
+/// Parse a position in any of the formats of a batch
+pub fn parse_position(line: &str) -> Result<Position> {
+    let tokens = line
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .collect_vec();
+
+    if tokens
+        .first()
+        .and_then(|token| token.parse::<Change>().ok())
+        .is_some()
+    {
+        let mut position = Position::solved();
+        for token in tokens {
+            let change = token.parse()?;
+            position = position.apply(change).ok_or_else(|| {
+                anyhow!("the change {} cannot be applied to {}", change, position)
+            })?;
+        }
+        Ok(position)
+    } else if line.split_whitespace().count() == 3 {
+        // The orientation of the middle layer is not part of positions, so it's ignored
+        let state: ViewerState = line.parse()?;
+        state
+            .position()
+            .context("the layers of the state cannot be sliced")
+    } else {
+        line.parse()
+    }
+}
+
+/// Read the positions of a batch, failing on the first line that can't be parsed
+pub fn read_positions(reader: impl BufRead) -> Result<Vec<BatchPosition>> {
+    let mut positions = vec![];
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        positions.push(BatchPosition {
+            line: n + 1,
+            position: parse_position(line).with_context(|| format!("invalid line {}", n + 1))?,
+        });
+    }
+    Ok(positions)
+}
+
+/// Solve every position with `solver`, in parallel in the current Rayon thread pool, which is
+/// shared by all the searches. The results are in the order of `positions`.
+///
+/// Each search can use several threads with [`crate::find_solution::SolverBuilder::num_threads()`],
+/// which is worth it when there are fewer positions than threads in the pool.
+pub fn solve_batch(positions: &[BatchPosition], solver: &Solver) -> Result<Vec<BatchResult>> {
+    positions
+        .par_iter()
+        .map(|&BatchPosition { line, position }| {
+            let (solutions, stats) = solver
+                .solve_many_with_stats(position)
+                .with_context(|| format!("failed to solve line {}", line))?;
+            Ok(BatchResult {
+                line,
+                position,
+                solution: solutions.into_iter().next(),
+                stats,
+            })
+        })
+        .collect()
+}
+
+impl Summary {
+    /// `None` if there are no values
+    pub fn new(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+
+        values.sort_by(|a, b| a.partial_cmp(b).expect("the values are not NaN"));
+        let middle = values.len() / 2;
+        let median = if values.len() % 2 == 1 {
+            values[middle]
+        } else {
+            (values[middle - 1] + values[middle]) / 2.0
+        };
+        Some(Summary {
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            median,
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+impl BatchSummary {
+    pub fn new(results: &[BatchResult]) -> Self {
+        let solutions = results
+            .iter()
+            .filter_map(|result| result.solution.as_ref())
+            .collect_vec();
+        BatchSummary {
+            num_positions: results.len(),
+            num_solved: solutions.len(),
+            num_optimal: solutions
+                .iter()
+                .filter(|solution| solution.is_optimal())
+                .count(),
+            length: Summary::new(
+                solutions
+                    .iter()
+                    .map(|solution| solution.num_movements() as f64)
+                    .collect(),
+            ),
+            seconds: Summary::new(
+                results
+                    .iter()
+                    .map(|result| result.stats.elapsed_seconds)
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for BatchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: ", self.line)?;
+        match &self.solution {
+            Some(solution) => {
+                write!(f, "{} movements", solution.num_movements())?;
+                if solution.is_optimal() {
+                    write!(f, " (optimal)")?;
+                }
+                write!(
+                    f,
+                    " in {:.2}s: {}",
+                    self.stats.elapsed_seconds,
+                    solution.movements()[1..]
+                        .iter()
+                        .map(|movement| movement.change())
+                        .format(", ")
+                )
+            }
+            None => write!(f, "no solution in {:.2}s", self.stats.elapsed_seconds),
+        }
+    }
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} positions solved, {} optimal",
+            self.num_solved, self.num_positions, self.num_optimal
+        )?;
+        if let Some(length) = self.length {
+            write!(
+                f,
+                ", length mean {:.2}, median {}, max {}",
+                length.mean, length.median, length.max
+            )?;
+        }
+        if let Some(seconds) = self.seconds {
+            write!(
+                f,
+                ", time mean {:.2}s, median {:.2}s, max {:.2}s",
+                seconds.mean, seconds.median, seconds.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_solution::test_solver;
+    use crate::scramble::scramble;
+
+    #[test]
+    fn formats() {
+        let movements = scramble(7, 4);
+        let position = movements[movements.len() - 1].position();
+
+        let changes = movements[1..]
+            .iter()
+            .map(|movement| movement.change())
+            .join(", ");
+        assert_eq!(parse_position(&changes).unwrap(), position);
+        assert_eq!(parse_position(&position.to_string()).unwrap(), position);
+        assert_eq!(
+            parse_position(&ViewerState::from_position(position).to_string()).unwrap(),
+            position
+        );
+
+        let batch = format!("# A comment\n\n{}\n  {}  \n", changes, position);
+        let positions = read_positions(batch.as_bytes()).unwrap();
+        assert_eq!(
+            positions.iter().map(|position| position.line).collect_vec(),
+            vec![3, 4]
+        );
+        assert!(read_positions("WRB WB\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn batch() {
+        let positions = (0..4)
+            .map(|seed| {
+                let movements = scramble(seed, 1);
+                BatchPosition {
+                    line: seed as usize + 1,
+                    position: movements[movements.len() - 1].position(),
+                }
+            })
+            .collect_vec();
+        let solver = test_solver().build();
+
+        let results = solve_batch(&positions, &solver).unwrap();
+        assert_eq!(
+            results.iter().map(|result| result.line).collect_vec(),
+            vec![1, 2, 3, 4]
+        );
+        for result in &results {
+            let solution = result.solution.as_ref().unwrap();
+            assert!(solution.is_optimal());
+            assert_eq!(solution.movements()[0].position(), result.position);
+        }
+
+        let summary = BatchSummary::new(&results);
+        assert_eq!(summary.num_positions, 4);
+        assert_eq!(summary.num_solved, 4);
+        assert_eq!(summary.num_optimal, 4);
+        let length = summary.length.unwrap();
+        assert!(length.median <= length.max && length.max <= 2.0);
+    }
+
+    #[test]
+    fn summary() {
+        assert!(Summary::new(vec![]).is_none());
+
+        let summary = Summary::new(vec![4.0, 1.0, 3.0, 2.0]).unwrap();
+        assert_eq!(summary.mean, 2.5);
+        assert_eq!(summary.median, 2.5);
+        assert_eq!(summary.max, 4.0);
+        assert_eq!(Summary::new(vec![5.0, 1.0, 2.0]).unwrap().median, 2.0);
+    }
+}
@@ -0,0 +1,53 @@
+//! Enumerate every state reachable from the solved position, printing how many states there are at
+//! each distance.
+//!
+//! Usage: `enumerate <compound|twist> [--directory DIR] [--memory-gib N] [--max-depth N]`
+
+use anyhow::{bail, Context, Result};
+use bachar_cube::enumeration::{print_progress, Enumeration, Metric};
+use bachar_cube::format_big_int;
+use std::env;
+use std::path::PathBuf;
+use std::time::Instant;
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let metric = match args.next().as_deref() {
+        Some("compound") => Metric::Compound,
+        Some("twist") => Metric::Twist,
+        _ => bail!("the first argument must be the metric: compound or twist"),
+    };
+
+    let mut directory = env::temp_dir().join("bachar-cube-enumeration");
+    let mut memory_gib = 8.0;
+    let mut max_depth = None;
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .with_context(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--directory" => directory = PathBuf::from(value),
+            "--memory-gib" => memory_gib = value.parse().context("invalid memory")?,
+            "--max-depth" => max_depth = Some(value.parse().context("invalid max depth")?),
+            _ => bail!("unknown argument {}", arg),
+        }
+    }
+
+    let memory_bytes = (memory_gib * (1u64 << 30) as f64) as usize;
+    let mut enumeration = Enumeration::new(metric, &directory, memory_bytes)?;
+    println!(
+        "Enumerating in the {:?} metric: {} possible states, paging into {}",
+        metric,
+        format_big_int(enumeration.num_states() as usize),
+        directory.display()
+    );
+
+    enumeration.run(max_depth, print_progress(Instant::now()))?;
+
+    println!("Distance distribution:");
+    for (depth, &count) in enumeration.distribution().iter().enumerate() {
+        println!("{}\t{}", depth, count);
+    }
+
+    Ok(())
+}
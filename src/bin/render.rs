@@ -0,0 +1,90 @@
+//! Draw a position as an SVG diagram, or each step of its solution.
+//!
+//! Usage: `render <POSITION> [--colours W=#eee,O=orange] [--size PIXELS] [--steps DIR SECONDS]`
+//!
+//! The position is in any of the formats of a batch, see `bachar_cube::batch`, and a state of the
+//! viewer keeps its middle layer. Without `--steps`, the diagram is printed on the standard
+//! output. With it, the state is solved within the time limit, including its middle layer, and a
+//! diagram of the state after each movement of the viewer is written to the directory.
+
+use anyhow::{bail, Context, Result};
+use bachar_cube::batch::parse_position;
+use bachar_cube::duration;
+use bachar_cube::find_solution::{Report, SearchMode, Solver};
+use bachar_cube::svg::{ColourScheme, SvgRenderer};
+use bachar_cube::viewer::ViewerState;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let input = args
+        .next()
+        .context("the first argument must be the position")?;
+
+    let mut scheme = ColourScheme::default();
+    let mut size = 200.0;
+    let mut steps = None;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--colours" => scheme = value()?.parse()?,
+            "--size" => size = value()?.parse().context("invalid size")?,
+            "--steps" => {
+                let directory = PathBuf::from(value()?);
+                let time_limit = value()?
+                    .parse()
+                    .ok()
+                    .and_then(duration)
+                    .context("invalid time limit")?;
+                steps = Some((directory, time_limit));
+            }
+            _ => bail!("unknown argument {}", arg),
+        }
+    }
+
+    let renderer = SvgRenderer::new(scheme).size(size);
+    let state = match input.parse::<ViewerState>() {
+        Ok(state) => state,
+        Err(_) => ViewerState::from_position(parse_position(&input)?),
+    };
+
+    let (directory, time_limit) = match steps {
+        Some(steps) => steps,
+        None => {
+            print!("{}", renderer.viewer_state(&state));
+            return Ok(());
+        }
+    };
+
+    // The state is solved as the viewer does, so that its middle layer is solved too
+    let solver = Solver::builder()
+        .mode(SearchMode::Anytime {
+            time_limit: Some(time_limit),
+        })
+        .report(Report::Silent)
+        .build();
+    let movements = state.solve(&solver)?;
+
+    fs::create_dir_all(&directory)
+        .with_context(|| format!("failed to create {}", directory.display()))?;
+    let mut state = state;
+    for n in 0..=movements.len() {
+        if n > 0 {
+            state.apply(movements[n - 1])?;
+        }
+        let path = directory.join(format!("step-{:02}.svg", n));
+        fs::write(&path, renderer.viewer_state(&state))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        match n {
+            0 => println!("{}", path.display()),
+            _ => println!("{}\t{}", path.display(), movements[n - 1]),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,587 @@
+//! A local HTTP service with JSON bodies, to call the solver from other tools. It only listens on
+//! the loopback interface.
+//!
+//! Usage: `server [--port N] [--workers N] [--max-jobs N] [--threads-per-job N]
+//! [--max-seen-positions N] [--max-time-limit SECONDS]`
+//!
+//! - `POST /solve` with `{"position": "WRB WB ...", "time_limit": 5, "max_movements": 20,
+//!   "symmetries": true}`, where only the position is required, returns the shortest solution
+//!   found within the time limit, or `null`
+//! - `POST /verify` with `{"position": "WRB WB ...", "changes": ["T1B0T3B2", ...]}` replays the
+//!   changes and tells whether they solve the position
+//! - `GET /scramble?seed=N&length=N` returns a reproducible random position
+//! - `GET /stats` returns the counters of the service
+//!
+//! Positions and changes are written like their `Display`. At most `--max-jobs` solves run at
+//! once, and the others are rejected with `503 Service Unavailable`. Each solve uses at most
+//! `--threads-per-job` threads, and stops like at its time limit once it has seen
+//! `--max-seen-positions` positions, which take about 40 bytes each with their node.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bachar_cube::duration;
+use bachar_cube::find_solution::{Report, SearchMode, Solver};
+use bachar_cube::position::{Change, Movement, Position};
+use bachar_cube::scramble::scramble;
+use bachar_cube::symmetry::SymmetryGroup;
+use itertools::Itertools;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Larger bodies are rejected before being parsed
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+/// The longest scramble that can be requested
+const MAX_SCRAMBLE_LENGTH: usize = 1_000;
+
+#[derive(Debug)]
+struct Service {
+    max_jobs: usize,
+    threads_per_job: usize,
+    max_seen_positions: usize,
+    max_time_limit: Duration,
+    /// The threads of the solves, so that they can't take more than their share of the machine
+    pool: ThreadPool,
+    start: Instant,
+    stats: Stats,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    requests: AtomicUsize,
+    active_jobs: AtomicUsize,
+    solves: AtomicUsize,
+    solutions: AtomicUsize,
+    rejected_jobs: AtomicUsize,
+    errors: AtomicUsize,
+}
+
+/// Frees the slot of a solve when it's dropped
+struct JobSlot<'a>(&'a AtomicUsize);
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SolveRequest {
+    position: String,
+    /// In seconds, at most the `--max-time-limit` of the service, which is also the default
+    time_limit: Option<f64>,
+    max_movements: Option<u16>,
+    #[serde(default = "default_symmetries")]
+    symmetries: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VerifyRequest {
+    position: String,
+    changes: Vec<String>,
+}
+
+/// The response of a failed request
+#[derive(Debug)]
+struct Failure {
+    status: u16,
+    message: String,
+}
+
+fn default_symmetries() -> bool {
+    true
+}
+
+fn main() -> Result<()> {
+    let mut port = 8080;
+    let mut workers = 4;
+    let mut max_jobs = 2;
+    let mut threads_per_job = 2;
+    let mut max_seen_positions = 20_000_000;
+    let mut max_time_limit = 60.0;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .with_context(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--port" => port = value.parse().context("invalid port")?,
+            "--workers" => workers = value.parse().context("invalid number of workers")?,
+            "--max-jobs" => max_jobs = value.parse().context("invalid number of jobs")?,
+            "--threads-per-job" => {
+                threads_per_job = value.parse().context("invalid number of threads")?;
+            }
+            "--max-seen-positions" => {
+                max_seen_positions = value.parse().context("invalid number of positions")?;
+            }
+            "--max-time-limit" => {
+                max_time_limit = value.parse().context("invalid time limit")?;
+            }
+            _ => bail!("unknown argument {}", arg),
+        }
+    }
+
+    let server = Arc::new(
+        Server::http(("127.0.0.1", port))
+            .map_err(|error| anyhow!("{}", error))
+            .context("failed to start the server")?,
+    );
+    let service = Arc::new(Service::new(
+        max_jobs,
+        threads_per_job,
+        max_seen_positions,
+        duration(max_time_limit).context("invalid time limit")?,
+    )?);
+    println!(
+        "Listening on http://127.0.0.1:{} with {} workers and at most {} solves at once, of {} \
+         threads each",
+        port, workers, max_jobs, service.threads_per_job
+    );
+
+    // Each worker handles one request at a time, so that slow solves don't block the other
+    // endpoints
+    let handles = (0..workers.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let service = Arc::clone(&service);
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    service.handle(request);
+                }
+            })
+        })
+        .collect_vec();
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    Ok(())
+}
+
+impl Service {
+    fn new(
+        max_jobs: usize,
+        threads_per_job: usize,
+        max_seen_positions: usize,
+        max_time_limit: Duration,
+    ) -> Result<Self> {
+        let threads_per_job = threads_per_job.max(1);
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(max_jobs.max(1) * threads_per_job)
+            .build()
+            .context("failed to start the threads of the solves")?;
+        Ok(Service {
+            max_jobs,
+            threads_per_job,
+            max_seen_positions,
+            max_time_limit,
+            pool,
+            start: Instant::now(),
+            stats: Stats::default(),
+        })
+    }
+
+    fn handle(&self, mut request: Request) {
+        let (status, body) = self.respond(&mut request);
+        let header =
+            Header::from_bytes("Content-Type", "application/json").expect("the header is valid");
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(header);
+        let url = request.url().to_string();
+        if let Err(error) = request.respond(response) {
+            eprintln!("Failed to respond to {}: {}", url, error);
+        }
+    }
+
+    /// The status and the body of the response to the request
+    fn respond(&self, request: &mut Request) -> (u16, Value) {
+        self.stats.requests.fetch_add(1, Ordering::Relaxed);
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+        let result = match (request.method(), path) {
+            (Method::Post, "/solve") => read_json(request).and_then(|body| self.solve(body)),
+            (Method::Post, "/verify") => read_json(request).and_then(verify),
+            (Method::Get, "/scramble") => get_scramble(query),
+            (Method::Get, "/stats") => Ok(self.stats()),
+            (_, "/solve" | "/verify" | "/scramble" | "/stats") => {
+                Err(Failure::new(405, "method not allowed"))
+            }
+            _ => Err(Failure::new(404, "not found")),
+        };
+
+        match result {
+            Ok(body) => (200, body),
+            Err(failure) => {
+                self.stats.errors.fetch_add(1, Ordering::Relaxed);
+                (failure.status, json!({ "error": failure.message }))
+            }
+        }
+    }
+
+    fn solve(&self, request: SolveRequest) -> Result<Value, Failure> {
+        let position: Position = request.position.parse().map_err(Failure::bad_request)?;
+        let time_limit = match request.time_limit {
+            None => self.max_time_limit,
+            Some(time_limit) => duration(time_limit)
+                .context("invalid time limit")
+                .map_err(Failure::bad_request)?
+                .min(self.max_time_limit),
+        };
+
+        let _slot = self.take_job_slot()?;
+        self.stats.solves.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        let solver = Solver::builder()
+            .num_threads(self.threads_per_job)
+            .max_seen_positions(self.max_seen_positions)
+            .mode(SearchMode::Multiple {
+                count: 1,
+                max_movements: request.max_movements,
+                time_limit: Some(time_limit),
+            })
+            .symmetries(if request.symmetries {
+                SymmetryGroup::Full
+            } else {
+                SymmetryGroup::Trivial
+            })
+            .report(Report::Silent)
+            .build();
+        let solution = self
+            .pool
+            .install(|| solver.solve(position))
+            .map_err(|error| Failure::new(500, format!("{:#}", error)))?;
+        let elapsed = start.elapsed();
+
+        let solution = solution.map(|solution| {
+            self.stats.solutions.fetch_add(1, Ordering::Relaxed);
+            json!({
+                "changes": changes_json(&solution.movements()[1..]),
+                "positions": positions_json(solution.movements()),
+                "num_movements": solution.num_movements(),
+                "is_optimal": solution.is_optimal(),
+            })
+        });
+        Ok(json!({
+            "position": position.to_string(),
+            "solution": solution,
+            "elapsed_seconds": elapsed.as_secs_f64(),
+        }))
+    }
+
+    fn take_job_slot(&self) -> Result<JobSlot<'_>, Failure> {
+        let active_jobs = &self.stats.active_jobs;
+        let mut current = active_jobs.load(Ordering::Relaxed);
+        loop {
+            if current >= self.max_jobs {
+                self.stats.rejected_jobs.fetch_add(1, Ordering::Relaxed);
+                return Err(Failure::new(503, "too many solves at once, retry later"));
+            }
+            match active_jobs.compare_exchange(
+                current,
+                current + 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Ok(JobSlot(active_jobs)),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    fn stats(&self) -> Value {
+        let stats = &self.stats;
+        json!({
+            "uptime_seconds": self.start.elapsed().as_secs_f64(),
+            "requests": stats.requests.load(Ordering::Relaxed),
+            "active_jobs": stats.active_jobs.load(Ordering::Relaxed),
+            "max_jobs": self.max_jobs,
+            "threads_per_job": self.threads_per_job,
+            "max_seen_positions": self.max_seen_positions,
+            "max_time_limit_seconds": self.max_time_limit.as_secs_f64(),
+            "solves": stats.solves.load(Ordering::Relaxed),
+            "solutions": stats.solutions.load(Ordering::Relaxed),
+            "rejected_jobs": stats.rejected_jobs.load(Ordering::Relaxed),
+            "errors": stats.errors.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Replay the changes from the position. The response tells how far they could be replayed, and
+/// whether they reach the solved position.
+fn verify(request: VerifyRequest) -> Result<Value, Failure> {
+    let position: Position = request.position.parse().map_err(Failure::bad_request)?;
+    let changes = request
+        .changes
+        .iter()
+        .map(|change| change.parse::<Change>())
+        .collect::<Result<Vec<_>>>()
+        .map_err(Failure::bad_request)?;
+
+    let mut current = position;
+    let mut error = None;
+    for (n, &change) in changes.iter().enumerate() {
+        match current.apply(change) {
+            Some(next) => current = next,
+            None => {
+                error = Some(format!(
+                    "the change {} at index {} is not possible from {}",
+                    change, n, current
+                ));
+                break;
+            }
+        }
+    }
+
+    Ok(json!({
+        "valid": error.is_none(),
+        "solved": error.is_none() && current == Position::solved(),
+        "final_position": current.to_string(),
+        "error": error,
+    }))
+}
+
+fn get_scramble(query: &str) -> Result<Value, Failure> {
+    let mut seed = None;
+    let mut length = 20;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let invalid = |_| Failure::new(400, format!("invalid {}: {}", key, value));
+        match key {
+            "seed" => seed = Some(value.parse().map_err(invalid)?),
+            "length" => length = value.parse().map_err(invalid)?,
+            _ => return Err(Failure::new(400, format!("unknown parameter {}", key))),
+        }
+    }
+    if length > MAX_SCRAMBLE_LENGTH {
+        return Err(Failure::new(
+            400,
+            format!("the length is at most {}", MAX_SCRAMBLE_LENGTH),
+        ));
+    }
+
+    // Without a seed, one is picked and returned, so that the scramble can be reproduced
+    let seed = seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+    });
+    let movements = scramble(seed, length);
+    Ok(json!({
+        "seed": seed,
+        "length": length,
+        "position": movements[movements.len() - 1].position().to_string(),
+        "changes": changes_json(&movements[1..]),
+    }))
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(request: &mut Request) -> Result<T, Failure> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_string(&mut body)
+        .map_err(|error| Failure::new(400, format!("failed to read the body: {}", error)))?;
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err(Failure::new(413, "the body is too large"));
+    }
+
+    serde_json::from_str(&body)
+        .map_err(|error| Failure::new(400, format!("invalid JSON body: {}", error)))
+}
+
+fn changes_json(movements: &[Movement]) -> Vec<String> {
+    movements
+        .iter()
+        .map(|movement| movement.change().to_string())
+        .collect()
+}
+
+fn positions_json(movements: &[Movement]) -> Vec<String> {
+    movements
+        .iter()
+        .map(|movement| movement.position().to_string())
+        .collect()
+}
+
+impl Failure {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Failure {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request(error: anyhow::Error) -> Self {
+        Failure::new(400, format!("{:#}", error))
+    }
+}
+
+impl Drop for JobSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_http::TestRequest;
+
+    fn new_service(max_jobs: usize) -> Service {
+        Service::new(max_jobs, 1, 1_000_000, Duration::from_secs(5)).unwrap()
+    }
+
+    fn request(service: &Service, method: Method, path: &str, body: String) -> (u16, Value) {
+        // The test requests only take static bodies
+        let body: &'static str = Box::leak(body.into_boxed_str());
+        let mut request = TestRequest::new()
+            .with_method(method)
+            .with_path(path)
+            .with_body(body)
+            .into();
+        service.respond(&mut request)
+    }
+
+    fn post(service: &Service, path: &str, body: Value) -> (u16, Value) {
+        request(service, Method::Post, path, body.to_string())
+    }
+
+    fn one_change_away() -> (Position, Change) {
+        let movements = scramble(3, 1);
+        (movements[1].position(), movements[1].change())
+    }
+
+    #[test]
+    fn bad_requests() {
+        let service = new_service(1);
+        let (status, body) = request(&service, Method::Post, "/solve", "{".to_string());
+        assert_eq!(status, 400);
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid JSON body"));
+
+        let (status, _) = post(&service, "/solve", json!({ "position": "WRB" }));
+        assert_eq!(status, 400);
+        let (status, _) = post(&service, "/solve", json!({ "positions": [] }));
+        assert_eq!(status, 400);
+        let (status, _) = request(&service, Method::Get, "/solve", String::new());
+        assert_eq!(status, 405);
+        let (status, _) = request(&service, Method::Get, "/unknown", String::new());
+        assert_eq!(status, 404);
+        let large = format!("\"{}\"", "a".repeat(MAX_BODY_BYTES as usize));
+        let (status, _) = request(&service, Method::Post, "/verify", large);
+        assert_eq!(status, 413);
+
+        assert_eq!(service.stats()["errors"], 6);
+    }
+
+    #[test]
+    fn verify_changes() {
+        let service = new_service(1);
+        let (position, change) = one_change_away();
+        let (status, body) = post(
+            &service,
+            "/verify",
+            json!({ "position": Position::solved().to_string(), "changes": [change.to_string()] }),
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body["valid"], true);
+        assert_eq!(body["solved"], false);
+        assert_eq!(body["final_position"], position.to_string());
+
+        let (status, body) = post(
+            &service,
+            "/verify",
+            json!({ "position": position.to_string(), "changes": [] }),
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body["solved"], false);
+
+        let (status, _) = post(
+            &service,
+            "/verify",
+            json!({ "position": position.to_string(), "changes": ["nope"] }),
+        );
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn scrambles() {
+        let service = new_service(1);
+        let (status, body) = request(
+            &service,
+            Method::Get,
+            "/scramble?seed=3&length=1",
+            String::new(),
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body["position"], one_change_away().0.to_string());
+        assert_eq!(body["changes"], json!([one_change_away().1.to_string()]));
+
+        let (status, _) = request(
+            &service,
+            Method::Get,
+            "/scramble?length=1001",
+            String::new(),
+        );
+        assert_eq!(status, 400);
+        let (status, _) = request(&service, Method::Get, "/scramble?seed=x", String::new());
+        assert_eq!(status, 400);
+        let (status, _) = request(&service, Method::Get, "/scramble?colour=red", String::new());
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn solve_within_the_job_slots() {
+        let service = new_service(1);
+        let (position, _) = one_change_away();
+        let body = json!({ "position": position.to_string(), "time_limit": 5 });
+
+        let (status, response) = post(&service, "/solve", body.clone());
+        assert_eq!(status, 200);
+        assert!(response["solution"]["changes"].is_array());
+        assert_eq!(service.stats.active_jobs.load(Ordering::Relaxed), 0);
+
+        // Another solve holds the only slot
+        let slot = service.take_job_slot().unwrap();
+        let (status, response) = post(&service, "/solve", body.clone());
+        assert_eq!(status, 503);
+        assert!(response["error"]
+            .as_str()
+            .unwrap()
+            .contains("too many solves"));
+        drop(slot);
+        assert_eq!(post(&service, "/solve", body.clone()).0, 200);
+
+        let (status, _) = post(&new_service(0), "/solve", body);
+        assert_eq!(status, 503);
+
+        let stats = service.stats();
+        assert_eq!(stats["solves"], 2);
+        assert_eq!(stats["solutions"], 2);
+        assert_eq!(stats["rejected_jobs"], 1);
+    }
+
+    #[test]
+    fn solves_stop_at_max_seen_positions() {
+        let service = Service::new(1, 1, 1, Duration::from_secs(60)).unwrap();
+        let movements = scramble(7, 20);
+        let position = movements[movements.len() - 1].position();
+
+        // The first expansion already reaches the limit, long before the time limit
+        let (status, response) = post(
+            &service,
+            "/solve",
+            json!({ "position": position.to_string() }),
+        );
+        assert_eq!(status, 200);
+        assert!(response["solution"].is_null());
+        assert!(response["elapsed_seconds"].as_f64().unwrap() < 10.0);
+    }
+}
@@ -0,0 +1,43 @@
+//! Summarize a trace recorded by the solver with `--trace`: the scores of the expanded positions
+//! by depth, and how many times the positions were expanded.
+//!
+//! Usage: `trace <FILE> [--depth-bucket N] [--json]`
+
+use anyhow::{bail, Context, Result};
+use bachar_cube::trace::{TraceReader, TraceSummary};
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let path = args
+        .next()
+        .context("the first argument must be the trace")?;
+
+    let mut depth_bucket = 100;
+    let mut json = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--depth-bucket" => {
+                depth_bucket = args
+                    .next()
+                    .context("missing value for --depth-bucket")?
+                    .parse()
+                    .context("invalid depth bucket")?;
+            }
+            "--json" => json = true,
+            _ => bail!("unknown argument {}", arg),
+        }
+    }
+
+    let file = File::open(&path).with_context(|| format!("failed to open {}", path))?;
+    let summary = TraceSummary::new(TraceReader::new(BufReader::new(file))?, depth_bucket)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        print!("{}", summary);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use crate::seen_set::SeenSet;
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Represent a set of [`u64`] with a Bloom filter, which uses a fixed amount of memory but can
+/// wrongly tell that a value is present.
+///
+/// Such a false positive makes the search reject a new position, which may hide the shortest
+/// solutions. To measure it, one value in 64 is also kept in an exact sample, and the false
+/// rejections of those values are extrapolated to all values. The sample takes a part of the
+/// memory of the filter, and keeps fewer values once it's full.
+///
+/// The depths are not stored, so a value is never inserted again at a shallower depth.
+#[derive(Debug)]
+pub struct BloomSet {
+    bits: Box<[AtomicU64]>,
+    num_hashes: u32,
+    num_set_bits: AtomicUsize,
+    len: AtomicUsize,
+    sample: Mutex<Sample>,
+}
+
+/// The sampled values, each with whether it was rejected by the filter when it was first inserted
+#[derive(Debug)]
+struct Sample {
+    values: HashMap<u64, bool>,
+    capacity: usize,
+    /// Only one value in `SAMPLING_RATE << shift` is sampled
+    shift: u32,
+    false_rejections: usize,
+}
+
+/// A power of two, so that sampling is a mask
+const SAMPLING_RATE: u64 = 64;
+
+/// The part of the memory that is used by the sample
+const SAMPLE_MEMORY_FRACTION: usize = 32;
+
+/// The memory of a sampled value in the worst case, when the hash map has twice the buckets it
+/// needs, each with a 16-byte entry and a control byte
+const SAMPLE_BYTES_PER_VALUE: usize = 40;
+
+impl BloomSet {
+    /// Create an empty filter of `memory_bytes`, setting `num_hashes` bits for each value. The
+    /// false positive rate is the smallest when `num_hashes` is about `0.7 * bits / values`.
+    pub fn new(memory_bytes: usize, num_hashes: u32) -> Self {
+        let sample_bytes = memory_bytes / SAMPLE_MEMORY_FRACTION;
+        let num_words = ((memory_bytes - sample_bytes) / 8).max(1);
+        let capacity = (sample_bytes / SAMPLE_BYTES_PER_VALUE).max(1);
+        BloomSet {
+            bits: (0..num_words).map(|_| AtomicU64::new(0)).collect(),
+            num_hashes: num_hashes.max(1),
+            num_set_bits: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            sample: Mutex::new(Sample {
+                values: HashMap::with_capacity(capacity),
+                capacity,
+                shift: 0,
+                false_rejections: 0,
+            }),
+        }
+    }
+
+    /// The probability that a value that was never inserted is considered present, given how
+    /// full the filter is
+    pub fn false_positive_rate(&self) -> f64 {
+        let num_bits = 64 * self.bits.len();
+        let fill_ratio = self.num_set_bits.load(Ordering::Relaxed) as f64 / num_bits as f64;
+        fill_ratio.powi(self.num_hashes as i32)
+    }
+
+    /// An estimation of how many new values were rejected because of false positives
+    pub fn false_rejections(&self) -> usize {
+        let sample = self.sample.lock();
+        sample.false_rejections * (SAMPLING_RATE << sample.shift) as usize
+    }
+}
+
+impl Sample {
+    fn is_sampled(&self, hash: u64) -> bool {
+        hash & ((SAMPLING_RATE << self.shift) - 1) == 0
+    }
+
+    fn insert(&mut self, value: u64, hash: u64, was_present: bool) {
+        if !self.is_sampled(hash) || self.values.contains_key(&value) {
+            return;
+        }
+        self.values.insert(value, was_present);
+        self.false_rejections += was_present as usize;
+
+        // Halve the rate until the sample fits, keeping the values that would have been sampled
+        // at the lower rate
+        while self.values.len() > self.capacity {
+            self.shift += 1;
+            let shift = self.shift;
+            self.values
+                .retain(|&value, _| mix(!value) & ((SAMPLING_RATE << shift) - 1) == 0);
+            self.false_rejections = self.values.values().filter(|&&rejected| rejected).count();
+        }
+    }
+}
+
+impl SeenSet for BloomSet {
+    fn insert(&self, value: u64, _depth: u16) -> Result<bool> {
+        let first_hash = mix(value);
+        let second_hash = mix(first_hash) | 1;
+        let num_bits = 64 * self.bits.len() as u64;
+
+        let mut was_present = true;
+        for i in 0..self.num_hashes as u64 {
+            let bit = first_hash.wrapping_add(i.wrapping_mul(second_hash)) % num_bits;
+            let mask = 1 << (bit % 64);
+            let word = self.bits[(bit / 64) as usize].fetch_or(mask, Ordering::Relaxed);
+            if word & mask == 0 {
+                was_present = false;
+                self.num_set_bits.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        // The sampling must not depend on the bits that are set, otherwise the sampled values
+        // would not be representative
+        let hash = mix(!value);
+        if hash & (SAMPLING_RATE - 1) == 0 {
+            self.sample.lock().insert(value, hash, was_present);
+        }
+
+        if !was_present {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(!was_present)
+    }
+
+    fn insert_if_shallower(&self, value: u64, depth: u16) -> Result<bool> {
+        self.insert(value, depth)
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn is_exact(&self) -> bool {
+        false
+    }
+
+    fn false_positive_rate(&self) -> Option<f64> {
+        Some(BloomSet::false_positive_rate(self))
+    }
+
+    fn false_rejections(&self) -> Option<usize> {
+        Some(BloomSet::false_rejections(self))
+    }
+}
+
+/// The finalizer of SplitMix64, so that every bit of the value affects every bit of the hash
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn false_positives() {
+        // 8 bits per value, a little less with the sample
+        let set = BloomSet::new(1 << 20, 5);
+        let values = (0..1u64 << 20).map(|n| n.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+        let accepted = values
+            .clone()
+            .filter(|&value| set.insert(value, 0).unwrap())
+            .count();
+        assert_eq!(accepted, set.len());
+        for value in values {
+            assert!(!set.insert(value, 0).unwrap());
+        }
+
+        // The theoretical rate is about 2% once full, and less while the filter was filling up.
+        // The sampling is a rough estimation of the actual rejections
+        let rejected = (1 << 20) - accepted;
+        assert!((0.01..0.04).contains(&set.false_positive_rate()));
+        assert!((2_000..20_000).contains(&rejected));
+        assert!(set.false_rejections() > rejected / 3 && set.false_rejections() < rejected * 3);
+
+        // The sample was too small for one value in 64, so it kept fewer of them
+        let sample = set.sample.lock();
+        assert!(sample.shift > 0);
+        assert!(sample.values.len() <= sample.capacity);
+        assert_eq!(
+            sample.capacity,
+            (1 << 20) / SAMPLE_MEMORY_FRACTION / SAMPLE_BYTES_PER_VALUE
+        );
+    }
+}
@@ -0,0 +1,520 @@
+use crate::seen_set::SeenSet;
+use crate::sharded_set::ShardedSet;
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use parking_lot::RwLock;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Represent a set of [`u64`] that can grow larger than the memory. Each value is stored with the
+/// smallest depth at which it was inserted.
+///
+/// New values go to a [`ShardedSet`] in memory. When it's full, it's written to the disk as a
+/// sorted run, and only a sparse index of the run stays in memory. The runs are merged by tiers:
+/// the runs written from memory are in the first tier, and once a tier has [`MERGE_RUNS`] runs,
+/// they are merged into a single run of the next tier. Each entry is rewritten once per tier, so
+/// the writes only grow as `n log(n)`, and there are at most `MERGE_RUNS - 1` runs per tier.
+/// Values are checked against the runs in batches, sorted so
+/// that each run is read in a single pass over the blocks that may hold them, and a batch reads at
+/// most one block of each run per value.
+///
+/// The memory still grows with the search: the sparse indexes take 8 bytes per block of the runs,
+/// and the search itself keeps about 8 bytes per node to rebuild the paths, and 16 bytes per node
+/// waiting in the queues.
+#[derive(Debug)]
+pub struct DiskSet {
+    directory: PathBuf,
+    max_memory_entries: usize,
+    state: RwLock<State>,
+    /// The number of entries in [`State::memory`]
+    memory_len: AtomicUsize,
+    len: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct State {
+    memory: ShardedSet,
+    runs: Vec<Run>,
+    next_run_id: usize,
+}
+
+/// A file of entries sorted by value, each value appearing once
+#[derive(Debug)]
+struct Run {
+    path: PathBuf,
+    file: File,
+    len: usize,
+    /// The first value of each block
+    index: Vec<u64>,
+    /// 0 for a run written from memory, and one more than the merged runs for a merged run
+    tier: u32,
+}
+
+#[derive(Debug)]
+struct RunWriter {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    len: usize,
+    index: Vec<u64>,
+    tier: u32,
+}
+
+/// Each entry is the value followed by the depth, in little endian
+const ENTRY_BYTES: usize = 10;
+
+/// The number of entries read from the disk for a lookup in a run
+const BLOCK_ENTRIES: usize = 256;
+
+/// The number of runs of a tier that are merged into a run of the next tier
+pub const MERGE_RUNS: usize = 4;
+
+/// The number of values checked against the runs at once by the search
+const BATCH_SIZE: usize = 1 << 14;
+
+/// The maximum number of consecutive blocks read at once when checking a batch
+const READ_BLOCKS: usize = 256;
+
+/// The memory used by each entry of a [`ShardedSet`], including the free slots
+const MEMORY_BYTES_PER_ENTRY: usize = 16;
+
+impl DiskSet {
+    /// Create an empty set that writes its runs inside `directory` and keeps at most
+    /// `memory_bytes` of recent entries in memory. The [`ShardedSet`] of the recent entries
+    /// takes [`ShardedSet::memory_bytes()`] even when it's empty, about 2 MB, which is counted in
+    /// `memory_bytes`: a smaller budget keeps a single entry in memory.
+    pub fn new(directory: &Path, memory_bytes: usize) -> Result<Self> {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("failed to create {}", directory.display()))?;
+
+        let memory = ShardedSet::new();
+        let entries_bytes = memory_bytes.saturating_sub(memory.memory_bytes());
+        Ok(DiskSet {
+            directory: directory.to_owned(),
+            max_memory_entries: (entries_bytes / MEMORY_BYTES_PER_ENTRY).max(1),
+            state: RwLock::new(State {
+                memory,
+                runs: vec![],
+                next_run_id: 0,
+            }),
+            memory_len: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+        })
+    }
+
+    /// The number of runs currently on the disk
+    pub fn num_runs(&self) -> usize {
+        self.state.read().runs.len()
+    }
+
+    /// Insert the values of a batch, checking them against each run in a single pass over the
+    /// blocks that may hold them
+    fn insert_batch_with(&self, entries: &[(u64, u16)], replace_deeper: bool) -> Result<Vec<bool>> {
+        let state = self.state.read();
+        let is_present = |present_depth: u16, depth: u16| !replace_deeper || present_depth <= depth;
+
+        // Sorted by value then depth, so that only the first occurrence of a value at its smallest
+        // depth is kept
+        let mut candidates = vec![];
+        for (i, &(value, depth)) in entries.iter().enumerate() {
+            let memory_depth = state.memory.depth(value);
+            if !matches!(memory_depth, Some(memory_depth) if is_present(memory_depth, depth)) {
+                candidates.push((value, depth, i, memory_depth.is_some()));
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup_by_key(|&mut (value, ..)| value);
+
+        let values = candidates.iter().map(|&(value, ..)| value).collect_vec();
+        let mut run_depths = vec![None; values.len()];
+        for run in &state.runs {
+            for (run_depth, depth) in run_depths.iter_mut().zip(run.depths(&values)?) {
+                if let Some(depth) = depth {
+                    *run_depth =
+                        Some(run_depth.map_or(depth, |run_depth: u16| run_depth.min(depth)));
+                }
+            }
+        }
+
+        let mut inserted = vec![false; entries.len()];
+        let mut should_flush = false;
+        for ((value, depth, i, in_memory), run_depth) in candidates.into_iter().zip(run_depths) {
+            if matches!(run_depth, Some(run_depth) if is_present(run_depth, depth)) {
+                continue;
+            }
+            inserted[i] = if replace_deeper {
+                state.memory.insert_if_shallower(value, depth)
+            } else {
+                state.memory.insert(value, depth)
+            };
+
+            // These counts can be off when several threads insert the same value at once, which
+            // only makes the next flush happen a little earlier
+            if inserted[i] && !in_memory {
+                let memory_len = self.memory_len.fetch_add(1, Ordering::Relaxed) + 1;
+                if run_depth.is_none() {
+                    self.len.fetch_add(1, Ordering::Relaxed);
+                }
+                should_flush |= memory_len > self.max_memory_entries;
+            }
+        }
+
+        drop(state);
+        if should_flush {
+            self.flush()?;
+        }
+        Ok(inserted)
+    }
+
+    /// Write the entries in memory to a new run, unless another thread already did it
+    fn flush(&self) -> Result<()> {
+        let mut state = self.state.write();
+        if self.memory_len.load(Ordering::Relaxed) <= self.max_memory_entries {
+            return Ok(());
+        }
+
+        let mut entries = state.memory.entries();
+        entries.sort_unstable();
+
+        let mut writer = RunWriter::new(self.run_path(&mut state), 0)?;
+        for (value, depth) in entries {
+            writer.push(value, depth)?;
+        }
+        state.runs.push(writer.finish()?);
+        state.memory.clear();
+        self.memory_len.store(0, Ordering::Relaxed);
+
+        // The tiers of the runs never increase, since a tier is merged as soon as it's full
+        loop {
+            let num_runs = state.runs.len();
+            if num_runs < MERGE_RUNS
+                || state.runs[num_runs - MERGE_RUNS].tier != state.runs[num_runs - 1].tier
+            {
+                break;
+            }
+
+            let path = self.run_path(&mut state);
+            let merged = merge(&state.runs[num_runs - MERGE_RUNS..], path)?;
+            let merged_runs = state.runs.split_off(num_runs - MERGE_RUNS);
+            state.runs.push(merged);
+            // A single run has no duplicates and the memory is empty, so it corrects the count
+            if state.runs.len() == 1 {
+                self.len.store(state.runs[0].len, Ordering::Relaxed);
+            }
+
+            for run in merged_runs {
+                // The entries are already in the merged run, so a file that can't be removed only
+                // wastes space on the disk
+                let path = run.path.clone();
+                if let Err(error) = run.remove() {
+                    log::warn!("failed to remove {}: {}", path.display(), error);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_path(&self, state: &mut State) -> PathBuf {
+        state.next_run_id += 1;
+        self.directory
+            .join(format!("seen-{}.bin", state.next_run_id - 1))
+    }
+}
+
+impl SeenSet for DiskSet {
+    fn insert(&self, value: u64, depth: u16) -> Result<bool> {
+        Ok(self.insert_batch_with(&[(value, depth)], false)?[0])
+    }
+
+    fn insert_if_shallower(&self, value: u64, depth: u16) -> Result<bool> {
+        Ok(self.insert_batch_with(&[(value, depth)], true)?[0])
+    }
+
+    fn insert_batch(&self, entries: &[(u64, u16)], replace_deeper: bool) -> Result<Vec<bool>> {
+        self.insert_batch_with(entries, replace_deeper)
+    }
+
+    fn batch_size(&self) -> usize {
+        BATCH_SIZE
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for DiskSet {
+    fn drop(&mut self) {
+        for run in self.state.get_mut().runs.drain(..) {
+            // The runs are useless once the search is over
+            let _ = run.remove();
+        }
+    }
+}
+
+impl Run {
+    /// The depth of each value, if it's present, for values sorted in increasing order. The
+    /// blocks that may hold the values are read in order, with a single read for up to
+    /// [`READ_BLOCKS`] consecutive blocks.
+    fn depths(&self, values: &[u64]) -> io::Result<Vec<Option<u16>>> {
+        // The block of each value, plus one, or 0 if it's before the first block
+        let blocks = values
+            .iter()
+            .map(|&value| self.index.partition_point(|&first| first <= value))
+            .collect_vec();
+        let mut depths = vec![None; values.len()];
+
+        let mut start = blocks.partition_point(|&block| block == 0);
+        while start < values.len() {
+            let mut end = start + 1;
+            while end < values.len()
+                && blocks[end] <= blocks[end - 1] + 1
+                && blocks[end] < blocks[start] + READ_BLOCKS
+            {
+                end += 1;
+            }
+
+            let first_entry = (blocks[start] - 1) * BLOCK_ENTRIES;
+            let end_entry = (blocks[end - 1] * BLOCK_ENTRIES).min(self.len);
+            let mut data = vec![0; (end_entry - first_entry) * ENTRY_BYTES];
+            self.file
+                .read_exact_at(&mut data, (first_entry * ENTRY_BYTES) as u64)?;
+
+            let mut entries = data.chunks_exact(ENTRY_BYTES).map(decode).peekable();
+            for (&value, depth) in values[start..end].iter().zip(&mut depths[start..end]) {
+                while entries.next_if(|&(entry, _)| entry < value).is_some() {}
+                if let Some(&(entry, entry_depth)) = entries.peek() {
+                    if entry == value {
+                        *depth = Some(entry_depth);
+                    }
+                }
+            }
+            start = end;
+        }
+
+        Ok(depths)
+    }
+
+    /// Read the entries through the open file, which stays readable even if its path is removed
+    fn entries(&self) -> io::Result<impl Iterator<Item = io::Result<(u64, u16)>>> {
+        // The cursor is shared with `file`, which is only read at explicit offsets
+        let mut file = self.file.try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(file);
+        Ok((0..self.len).map(move |_| {
+            let mut entry = [0; ENTRY_BYTES];
+            reader.read_exact(&mut entry)?;
+            Ok(decode(&entry))
+        }))
+    }
+
+    fn remove(self) -> io::Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+    }
+}
+
+impl RunWriter {
+    fn new(path: PathBuf, tier: u32) -> Result<Self> {
+        let file =
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+        Ok(RunWriter {
+            path,
+            writer: BufWriter::new(file),
+            len: 0,
+            index: vec![],
+            tier,
+        })
+    }
+
+    /// Append an entry, with a value greater than the previous one
+    fn push(&mut self, value: u64, depth: u16) -> io::Result<()> {
+        if self.len == self.index.len() * BLOCK_ENTRIES {
+            self.index.push(value);
+        }
+        self.len += 1;
+
+        self.writer.write_all(&value.to_le_bytes())?;
+        self.writer.write_all(&depth.to_le_bytes())
+    }
+
+    fn finish(self) -> Result<Run> {
+        self.writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        let file = File::open(&self.path)
+            .with_context(|| format!("failed to open {}", self.path.display()))?;
+
+        Ok(Run {
+            path: self.path,
+            file,
+            len: self.len,
+            index: self.index,
+            tier: self.tier,
+        })
+    }
+}
+
+/// Merge the runs into a single one of the next tier, keeping the smallest depth of each value
+fn merge(runs: &[Run], path: PathBuf) -> Result<Run> {
+    let mut readers = runs
+        .iter()
+        .map(Run::entries)
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut heap = BinaryHeap::new();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        if let Some(entry) = reader.next() {
+            let (value, depth) = entry?;
+            heap.push(Reverse((value, depth, i)));
+        }
+    }
+
+    let tier = runs.iter().map(|run| run.tier).max().unwrap_or(0) + 1;
+    let mut writer = RunWriter::new(path, tier)?;
+    let mut last_value = None;
+    while let Some(Reverse((value, depth, i))) = heap.pop() {
+        // Entries with the same value come out from the smallest depth
+        if last_value != Some(value) {
+            writer.push(value, depth)?;
+            last_value = Some(value);
+        }
+
+        if let Some(entry) = readers[i].next() {
+            let (value, depth) = entry?;
+            heap.push(Reverse((value, depth, i)));
+        }
+    }
+
+    writer.finish()
+}
+
+fn decode(entry: &[u8]) -> (u64, u16) {
+    let mut value = [0; 8];
+    value.copy_from_slice(&entry[..8]);
+    let mut depth = [0; 2];
+    depth.copy_from_slice(&entry[8..]);
+    (u64::from_le_bytes(value), u16::from_le_bytes(depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// A set that keeps `entries` in memory
+    fn with_entries(directory: &Path, entries: usize) -> DiskSet {
+        let memory_bytes = ShardedSet::new().memory_bytes() + entries * MEMORY_BYTES_PER_ENTRY;
+        DiskSet::new(directory, memory_bytes).unwrap()
+    }
+
+    #[test]
+    fn insert_and_merge() {
+        let directory = tempfile::tempdir().unwrap();
+        // Only 100 entries in memory, so that many runs are written and merged
+        let set = with_entries(directory.path(), 100);
+
+        let values = (0..2_000u64).map(|n| n.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        for (n, value) in values.clone().enumerate() {
+            assert!(set.insert_if_shallower(value, (n % 10) as u16 + 1).unwrap());
+        }
+        assert!(set.num_runs() < 3 * MERGE_RUNS);
+        assert_eq!(set.len(), 2_000);
+
+        for (n, value) in values.enumerate() {
+            let depth = (n % 10) as u16 + 1;
+            assert!(!set.insert(value, 0).unwrap());
+            assert!(!set.insert_if_shallower(value, depth).unwrap());
+            assert!(set.insert_if_shallower(value, depth - 1).unwrap());
+        }
+        assert_eq!(set.len(), 2_000);
+    }
+
+    #[test]
+    fn merge_by_tiers() {
+        let directory = tempfile::tempdir().unwrap();
+        // A run is written at every 101 values
+        let set = with_entries(directory.path(), 100);
+        for n in 0..101 * 22u64 {
+            set.insert(n.wrapping_mul(0x9E37_79B9_7F4A_7C15), 1)
+                .unwrap();
+        }
+
+        // 22 runs were written from memory, which is 1 * 4^2 + 1 * 4 + 2 in base 4
+        let state = set.state.read();
+        let tiers = state.runs.iter().map(|run| run.tier).collect_vec();
+        assert_eq!(tiers, vec![2, 1, 0, 0]);
+        let lens = state.runs.iter().map(|run| run.len).collect_vec();
+        assert_eq!(lens, vec![101 * 16, 101 * 4, 101, 101]);
+        drop(state);
+        assert_eq!(set.len(), 101 * 22);
+    }
+
+    #[test]
+    fn keep_merged_runs_that_cannot_be_removed() {
+        let directory = tempfile::tempdir().unwrap();
+        let set = with_entries(directory.path(), 100);
+        let values = (0..2_000u64).map(|n| n.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+        for (n, value) in values.clone().enumerate() {
+            assert!(set.insert(value, 1).unwrap());
+            // Removing the first run fails when it's merged
+            if n == 200 {
+                fs::remove_file(directory.path().join("seen-0.bin")).unwrap();
+            }
+        }
+
+        assert_eq!(set.len(), 2_000);
+        for value in values {
+            assert!(!set.insert(value, 1).unwrap());
+        }
+    }
+
+    #[test]
+    fn insert_batches() {
+        let directory = tempfile::tempdir().unwrap();
+        let set = with_entries(directory.path(), 100);
+        // The smallest depth of each value inserted so far
+        let mut depths = HashMap::new();
+
+        for batch in 0..40u64 {
+            // Values repeat within a batch and across the batches, which end up in different runs
+            let entries = (0..300u64)
+                .map(|n| {
+                    let value = (batch * 150 + n) % 1_000;
+                    (
+                        value.wrapping_mul(0x9E37_79B9_7F4A_7C15),
+                        ((n * 7 + batch) % 5) as u16,
+                    )
+                })
+                .collect_vec();
+            let replace_deeper = batch % 2 == 0;
+            let inserted = set.insert_batch(&entries, replace_deeper).unwrap();
+
+            // Only the first occurrence of a value at its smallest depth in the batch can be
+            // inserted
+            let mut expected = vec![false; entries.len()];
+            let mut order = (0..entries.len()).collect_vec();
+            order.sort_by_key(|&i| entries[i]);
+            for i in order {
+                let (value, depth) = entries[i];
+                expected[i] = match depths.get(&value) {
+                    None => true,
+                    Some(&present) => replace_deeper && depth < present,
+                };
+                if expected[i] {
+                    depths.insert(value, depth);
+                }
+            }
+            assert_eq!(inserted, expected, "batch {}", batch);
+        }
+
+        assert!(set.num_runs() > 0);
+        assert_eq!(set.len(), depths.len());
+    }
+}
@@ -0,0 +1,495 @@
+use crate::format_big_int;
+use crate::position::NeighboursStack;
+use crate::ranking::{NUM_POSITIONS, NUM_STATES};
+use crate::Position;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// How the distance between two states is measured
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Metric {
+    /// Each [`crate::position::Change`] counts as one move. The middle layer is flipped twice by
+    /// each move, so it's never part of the state.
+    Compound,
+    /// Rotating any layers and then flipping once counts as one move. The orientation of the
+    /// middle layer is part of the state.
+    Twist,
+}
+
+/// Breadth-first enumeration of every state reachable from [`Position::solved()`].
+///
+/// The distance of each state is kept in a [`DistanceTable`], indexed by [`Position::rank()`] or
+/// [`Position::rank_with_middle()`], which is paged to disk when it does not fit in the memory
+/// budget.
+#[derive(Debug)]
+pub struct Enumeration {
+    metric: Metric,
+    table: DistanceTable,
+    /// The number of states found at each depth
+    distribution: Vec<u64>,
+}
+
+/// A table of 2-bit entries, split into pages that are written to a file when more than
+/// `max_pages` would be in memory.
+///
+/// Pages that were never written are all zeros and take no space, neither in memory nor on disk.
+#[derive(Debug)]
+pub struct DistanceTable {
+    path: PathBuf,
+    file: Option<File>,
+    page_bytes: usize,
+    max_pages: usize,
+    pages: HashMap<u64, Page>,
+    /// One bit for each page, set if it was ever written to the file
+    stored_pages: Vec<u64>,
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Page {
+    data: Vec<u8>,
+    is_dirty: bool,
+    last_used: u64,
+}
+
+/// Small pages avoid writing mostly empty pages to disk while the reached states are still sparse
+const PAGE_BYTES: usize = 256 << 10;
+
+/// How many table entries are expanded by a single parallel task
+const SCAN_CHUNK_BYTES: usize = 1 << 12;
+
+/// How many table entries are expanded before the buffered updates are deduplicated
+const SCAN_BATCH_BYTES: usize = 1 << 16;
+
+/// Each entry is 2 bits: [`UNSEEN`], [`DONE`], or the [`frontier_code()`] of the depth of the
+/// state while it's at the current or the next depth
+const ENTRIES_PER_BYTE: u64 = 4;
+
+/// The state was not reached yet
+const UNSEEN: u8 = 0;
+/// The state was reached and expanded
+const DONE: u8 = 1;
+
+impl Enumeration {
+    /// Prepare an enumeration that keeps at most `memory_bytes` of its table in memory, paging the
+    /// rest into a file inside `directory`
+    pub fn new(metric: Metric, directory: &Path, memory_bytes: usize) -> Result<Self> {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("failed to create {}", directory.display()))?;
+
+        let page_bytes = PAGE_BYTES;
+        let table = DistanceTable::new(
+            directory.join("distances.bin"),
+            page_bytes,
+            (memory_bytes / page_bytes).max(2),
+        );
+
+        Ok(Enumeration {
+            metric,
+            table,
+            distribution: vec![],
+        })
+    }
+
+    pub fn num_states(&self) -> u64 {
+        match self.metric {
+            Metric::Compound => NUM_POSITIONS,
+            Metric::Twist => NUM_STATES,
+        }
+    }
+
+    /// The number of states found at each depth so far
+    pub fn distribution(&self) -> &[u64] {
+        &self.distribution
+    }
+
+    /// Run the enumeration until no new state is found or `max_depth` is reached, calling
+    /// `on_depth` after each depth is complete
+    pub fn run(
+        &mut self,
+        max_depth: Option<usize>,
+        mut on_depth: impl FnMut(usize, u64),
+    ) -> Result<()> {
+        if self.distribution.is_empty() {
+            let solved = match self.metric {
+                Metric::Compound => Position::solved().rank(),
+                Metric::Twist => Position::solved().rank_with_middle(false),
+            };
+            self.table.set(solved, frontier_code(0))?;
+            self.distribution.push(1);
+            on_depth(0, 1);
+        }
+
+        while self.distribution.last() != Some(&0) {
+            let depth = self.distribution.len() - 1;
+            if matches!(max_depth, Some(max_depth) if depth >= max_depth) {
+                break;
+            }
+
+            let found = self.expand_depth(depth)?;
+            self.distribution.push(found);
+            on_depth(depth + 1, found);
+        }
+
+        if self.distribution.last() == Some(&0) {
+            self.distribution.pop();
+        }
+
+        Ok(())
+    }
+
+    /// Expand every state at the given depth, returning how many new states were found. They are
+    /// marked with the frontier code of the next depth, while the states of this depth are marked
+    /// as done once their page is read.
+    fn expand_depth(&mut self, depth: usize) -> Result<u64> {
+        let current_code = frontier_code(depth);
+        let next_code = frontier_code(depth + 1);
+        let max_updates = (self.table.page_bytes * 4).max(1 << 20);
+
+        let mut found = 0;
+        let mut updates = vec![];
+        for page in self.table.written_pages() {
+            let data = self.table.page_copy(page)?;
+            self.table.replace(page, current_code, DONE)?;
+
+            let page_start = page * self.table.page_bytes as u64 * ENTRIES_PER_BYTE;
+            for (batch_index, batch) in data.chunks(SCAN_BATCH_BYTES).enumerate() {
+                let batch_start =
+                    page_start + (batch_index * SCAN_BATCH_BYTES) as u64 * ENTRIES_PER_BYTE;
+
+                let mut batch_updates: Vec<u64> = batch
+                    .par_chunks(SCAN_CHUNK_BYTES)
+                    .enumerate()
+                    .map_init(NeighboursStack::new, |stack, (chunk_index, chunk)| {
+                        let chunk_start = batch_start
+                            + (chunk_index * SCAN_CHUNK_BYTES) as u64 * ENTRIES_PER_BYTE;
+                        let mut chunk_updates = vec![];
+                        for (byte_index, &byte) in chunk.iter().enumerate() {
+                            // A quick check for the bytes without any entry of a frontier
+                            if byte & 0b1010_1010 == 0 {
+                                continue;
+                            }
+
+                            for slot in 0..ENTRIES_PER_BYTE {
+                                if (byte >> (2 * slot)) & 0b11 == current_code {
+                                    let index =
+                                        chunk_start + byte_index as u64 * ENTRIES_PER_BYTE + slot;
+                                    self.expand_state(index, stack, &mut chunk_updates);
+                                }
+                            }
+                        }
+                        chunk_updates
+                    })
+                    .flatten()
+                    .collect();
+
+                batch_updates.par_sort_unstable();
+                batch_updates.dedup();
+                updates.extend(batch_updates);
+
+                if updates.len() > max_updates {
+                    found += self.apply_updates(&mut updates, next_code)?;
+                }
+            }
+        }
+        found += self.apply_updates(&mut updates, next_code)?;
+
+        Ok(found)
+    }
+
+    fn expand_state(&self, index: u64, stack: &mut NeighboursStack, updates: &mut Vec<u64>) {
+        match self.metric {
+            Metric::Compound => {
+                let position = Position::unrank(index);
+                position.neighbours(stack);
+                updates.extend(
+                    stack
+                        .neighbours()
+                        .iter()
+                        .map(|movement| movement.position().rank()),
+                );
+            }
+            Metric::Twist => {
+                let (position, middle_flipped) = Position::unrank_with_middle(index);
+                position.twists(stack);
+                updates.extend(
+                    stack
+                        .twists()
+                        .iter()
+                        .map(|&twisted| twisted.rank_with_middle(!middle_flipped)),
+                );
+            }
+        }
+    }
+
+    /// Mark the states that were not reached yet, returning how many there were
+    fn apply_updates(&mut self, updates: &mut Vec<u64>, next_code: u8) -> Result<u64> {
+        updates.par_sort_unstable();
+        updates.dedup();
+
+        let mut found = 0;
+        for &index in updates.iter() {
+            if self.table.get(index)? == UNSEEN {
+                self.table.set(index, next_code)?;
+                found += 1;
+            }
+        }
+
+        updates.clear();
+        Ok(found)
+    }
+}
+
+/// The code of the states at `depth` until they are expanded. It alternates between two values, so
+/// that the states found at the next depth are not expanded with the current ones.
+fn frontier_code(depth: usize) -> u8 {
+    2 + (depth % 2) as u8
+}
+
+impl DistanceTable {
+    fn new(path: PathBuf, page_bytes: usize, max_pages: usize) -> Self {
+        DistanceTable {
+            path,
+            file: None,
+            page_bytes,
+            max_pages,
+            pages: HashMap::new(),
+            stored_pages: vec![],
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, index: u64) -> Result<u8> {
+        let (page, byte, shift) = self.locate(index);
+        Ok((self.page_mut(page)?.data[byte] >> shift) & 0b11)
+    }
+
+    fn set(&mut self, index: u64, value: u8) -> Result<()> {
+        let (page, byte, shift) = self.locate(index);
+        let page = self.page_mut(page)?;
+        page.data[byte] = (page.data[byte] & !(0b11 << shift)) | (value << shift);
+        page.is_dirty = true;
+        Ok(())
+    }
+
+    /// Mark the entries of a page that have the code `from` with the code `to`
+    fn replace(&mut self, page: u64, from: u8, to: u8) -> Result<()> {
+        let page = self.page_mut(page)?;
+        for byte in page.data.iter_mut() {
+            if *byte == 0 {
+                continue;
+            }
+            for slot in 0..ENTRIES_PER_BYTE {
+                let shift = 2 * slot;
+                if (*byte >> shift) & 0b11 == from {
+                    *byte = (*byte & !(0b11 << shift)) | (to << shift);
+                    page.is_dirty = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The pages that may have non-zero entries, in order
+    fn written_pages(&self) -> Vec<u64> {
+        let stored_pages = self
+            .stored_pages
+            .iter()
+            .enumerate()
+            .filter(|&(_, &word)| word != 0)
+            .flat_map(|(word_index, &word)| {
+                (0..64)
+                    .filter(move |bit| (word >> bit) & 1 == 1)
+                    .map(move |bit| 64 * word_index as u64 + bit)
+            });
+        let mut pages: Vec<u64> = self.pages.keys().copied().chain(stored_pages).collect();
+        pages.sort_unstable();
+        pages.dedup();
+        pages
+    }
+
+    fn page_copy(&mut self, page: u64) -> Result<Vec<u8>> {
+        Ok(self.page_mut(page)?.data.clone())
+    }
+
+    fn locate(&self, index: u64) -> (u64, usize, u32) {
+        let entries_per_page = self.page_bytes as u64 * ENTRIES_PER_BYTE;
+        let page = index / entries_per_page;
+        let offset = index % entries_per_page;
+        let byte = (offset / ENTRIES_PER_BYTE) as usize;
+        let shift = 2 * (offset % ENTRIES_PER_BYTE) as u32;
+        (page, byte, shift)
+    }
+
+    fn page_mut(&mut self, page: u64) -> Result<&mut Page> {
+        self.clock += 1;
+
+        if !self.pages.contains_key(&page) {
+            if self.pages.len() >= self.max_pages {
+                self.evict()?;
+            }
+
+            let mut data = vec![0; self.page_bytes];
+            if self.is_stored(page) {
+                let file = self.file()?;
+                file.seek(SeekFrom::Start(page * data.len() as u64))?;
+                file.read_exact(&mut data)?;
+            }
+
+            self.pages.insert(
+                page,
+                Page {
+                    data,
+                    is_dirty: false,
+                    last_used: 0,
+                },
+            );
+        }
+
+        let clock = self.clock;
+        let page = self.pages.get_mut(&page).unwrap();
+        page.last_used = clock;
+        Ok(page)
+    }
+
+    /// Remove the least recently used page from memory, writing it to disk if needed
+    fn evict(&mut self) -> Result<()> {
+        let (&page, _) = self
+            .pages
+            .iter()
+            .min_by_key(|(_, page)| page.last_used)
+            .context("no page to evict")?;
+        let evicted = self.pages.remove(&page).unwrap();
+
+        if evicted.is_dirty {
+            let file = self.file()?;
+            file.seek(SeekFrom::Start(page * evicted.data.len() as u64))?;
+            file.write_all(&evicted.data)?;
+
+            let word = (page / 64) as usize;
+            if word >= self.stored_pages.len() {
+                self.stored_pages.resize(word + 1, 0);
+            }
+            self.stored_pages[word] |= 1 << (page % 64);
+        }
+
+        Ok(())
+    }
+
+    fn is_stored(&self, page: u64) -> bool {
+        let word = self.stored_pages.get((page / 64) as usize).copied();
+        (word.unwrap_or(0) >> (page % 64)) & 1 == 1
+    }
+
+    fn file(&mut self) -> Result<&mut File> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&self.path)
+                .with_context(|| format!("failed to create {}", self.path.display()))?;
+            self.file = Some(file);
+        }
+
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+/// Print the distribution of distances after each depth is complete
+pub fn print_progress(start: Instant) -> impl FnMut(usize, u64) {
+    let mut total = 0;
+    move |depth, found| {
+        total += found;
+        println!(
+            "Depth {}: {} new states, {} in total, after {:.1?}",
+            depth,
+            format_big_int(found as usize),
+            format_big_int(total as usize),
+            start.elapsed()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn first_depths() {
+        for (metric, depths) in [(Metric::Compound, 1), (Metric::Twist, 3)] {
+            let directory = tempfile::tempdir().unwrap();
+            let mut enumeration = Enumeration::new(metric, directory.path(), 0).unwrap();
+            // Use tiny pages so that they are written to and read from the disk
+            enumeration.table =
+                DistanceTable::new(directory.path().join("distances.bin"), 1 << 10, 8);
+            enumeration.run(Some(depths), |_, _| {}).unwrap();
+
+            // Check against a naive breadth-first search
+            let mut stack = NeighboursStack::new();
+            let mut seen = BTreeSet::new();
+            let mut frontier = vec![(Position::solved(), false)];
+            seen.insert(frontier[0]);
+            let mut distribution = vec![1];
+            for _ in 0..depths {
+                let mut next_frontier = vec![];
+                for (position, middle_flipped) in frontier {
+                    let next_states = match metric {
+                        Metric::Compound => {
+                            position.neighbours(&mut stack);
+                            stack
+                                .neighbours()
+                                .iter()
+                                .map(|m| (m.position(), false))
+                                .collect::<Vec<_>>()
+                        }
+                        Metric::Twist => {
+                            position.twists(&mut stack);
+                            stack
+                                .twists()
+                                .iter()
+                                .map(|&p| (p, !middle_flipped))
+                                .collect()
+                        }
+                    };
+                    for state in next_states {
+                        if seen.insert(state) {
+                            next_frontier.push(state);
+                        }
+                    }
+                }
+                distribution.push(next_frontier.len() as u64);
+                frontier = next_frontier;
+            }
+
+            assert_eq!(enumeration.distribution(), distribution);
+
+            // Only the states of the last depth are left to expand
+            let mut num_codes = [0; 4];
+            for page in enumeration.table.written_pages() {
+                for &byte in &enumeration.table.page_copy(page).unwrap() {
+                    if byte == 0 {
+                        continue;
+                    }
+                    for slot in 0..ENTRIES_PER_BYTE {
+                        num_codes[((byte >> (2 * slot)) & 0b11) as usize] += 1;
+                    }
+                }
+            }
+            let num_reached: u64 = distribution.iter().sum();
+            assert_eq!(
+                num_codes[frontier_code(depths) as usize],
+                distribution[depths]
+            );
+            assert_eq!(num_codes[DONE as usize], num_reached - distribution[depths]);
+            assert_eq!(num_codes[frontier_code(depths + 1) as usize], 0);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! A walkthrough of a solution, to follow it on a physical puzzle.
+//!
+//! Each change of a solution is split into its twists: a turn of each layer, then a flip of half
+//! of the puzzle, unless the change only turns the layers since its flips cancel out. The twists
+//! are written in the usual notation, like `(1,-3)/`, where the turns are in twelfths of a full
+//! turn, clockwise as seen from each layer and between -5 and 6, and `/` is the flip. They are
+//! also written in the movements of the viewer in `web3d/`, see [`crate::viewer`].
+
+use crate::find_solution::Solution;
+use crate::position::Change;
+use crate::simplify::Twist;
+use crate::viewer::{ViewerMovement, ViewerState};
+use crate::Position;
+use anyhow::{ensure, Result};
+use itertools::Itertools;
+use serde::Serialize;
+use std::fmt;
+
+/// A twist of a solution and the position it leads to
+#[derive(Debug, Clone, Serialize)]
+pub struct Step {
+    /// The number of the change that the twist is part of, from 1
+    pub change_index: usize,
+    pub change: Change,
+    /// The twist in the usual notation, like `(1,-3)/`
+    pub notation: String,
+    /// The twist in the movements of the viewer, like `RotateTop(1), RotateBottom(9), Flip`
+    pub viewer: String,
+    pub position: Position,
+    pub score: u8,
+    /// The name of the shape, like `Kite/Square`
+    pub shape: String,
+    /// Whether the middle layer is turned, as it is between the two flips of a change
+    pub middle_flipped: bool,
+}
+
+/// Split each change of the solution into its twists
+pub fn explain(solution: &Solution) -> Result<Vec<Step>> {
+    let movements = solution.movements();
+    let mut state = ViewerState::from_position(movements[0].position());
+    let mut steps = vec![];
+
+    for (n, movement) in movements[1..].iter().enumerate() {
+        let change = movement.change();
+        let mut viewer_movements = vec![];
+        state.clone().push_change(change, &mut viewer_movements)?;
+        if change.is_rotation() {
+            viewer_movements.retain(|&movement| movement != ViewerMovement::Flip);
+        }
+
+        for twist in viewer_movements.split_inclusive(|&movement| movement == ViewerMovement::Flip)
+        {
+            let (mut top, mut bottom, mut flip) = (0, 0, false);
+            for &movement in twist {
+                match movement {
+                    ViewerMovement::RotateTop(units) => top += units,
+                    ViewerMovement::RotateBottom(units) => bottom += units,
+                    ViewerMovement::Flip => flip = true,
+                }
+                state.apply(movement)?;
+            }
+
+            let position = state
+                .position()
+                .expect("the layers can be sliced after a twist");
+            steps.push(Step {
+                change_index: n + 1,
+                change,
+                notation: Twist::new(top as i8, bottom as i8, flip).to_string(),
+                viewer: twist.iter().join(", "),
+                position,
+                score: position.score(),
+                shape: position.shape().to_string(),
+                middle_flipped: !state.is_middle_solved(),
+            });
+        }
+
+        ensure!(
+            state == ViewerState::from_position(movement.position()),
+            "the twists of {} lead to {} instead of {}",
+            change,
+            state,
+            movement.position()
+        );
+    }
+
+    Ok(steps)
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>3}. {:<9} {:<38} score {:>2}, {}",
+            self.change_index, self.notation, self.viewer, self.score, self.shape
+        )?;
+        if self.middle_flipped {
+            write!(f, ", middle flipped")?;
+        }
+        write!(f, "\n     {}", self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_solution::solve_small;
+    use crate::scramble::scramble;
+
+    #[test]
+    fn explain_solution() {
+        let movements = scramble(5, 1);
+        let position = movements[movements.len() - 1].position();
+        let solution = solve_small(position);
+
+        let steps = explain(&solution).unwrap();
+        let num_twists: usize = solution.movements()[1..]
+            .iter()
+            .map(|movement| movement.change().num_flips().max(1))
+            .sum();
+        assert_eq!(steps.len(), num_twists);
+
+        // The middle layer is only turned between the flips of a change
+        for (n, movement) in solution.movements()[1..].iter().enumerate() {
+            let twists = steps
+                .iter()
+                .filter(|step| step.change_index == n + 1)
+                .collect_vec();
+            let last = twists[twists.len() - 1];
+            assert_eq!(last.position, movement.position());
+            assert!(!last.middle_flipped);
+            if twists.len() == 2 {
+                assert!(twists[0].middle_flipped);
+                assert!(twists[0].notation.ends_with('/'));
+            }
+        }
+
+        let last = &steps[steps.len() - 1];
+        assert_eq!(last.score, Position::solved().score());
+        assert_eq!(last.shape, "Square/Square");
+    }
+}
@@ -1,128 +1,621 @@
-use crate::position::{Movement, NeighboursStack};
-use crate::prefix_set::PrefixSet;
+use crate::position::{Change, Movement, NeighboursStack, PruningStats};
+use crate::seen_set::{SeenSet, SeenStorage};
+use crate::symmetry::SymmetryGroup;
+use crate::trace::{TraceRecord, TraceWriter};
 use crate::{format_big_int, Position};
+use anyhow::{anyhow, Context, Result};
 use crossbeam_utils::atomic::AtomicCell;
 use itertools::Itertools;
-use parking_lot::Mutex;
+use log::Level;
+use parking_lot::{Condvar, Mutex};
 use rayon::prelude::*;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 
-#[derive(Debug, Clone, Copy)]
-struct VisitedPosition {
-    movement: Movement,
-    prev_index: Option<u32>,
+/// How the search behaves once a solution is found
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SearchMode {
+    /// Stop as soon as any solution is found
+    FirstSolution,
+    /// Keep searching for shorter solutions, pruning every node that cannot improve the best one
+    /// found so far. The search stops when the time limit is reached or when the frontier is
+    /// exhausted, in which case the best solution is proven to be optimal.
+    Anytime { time_limit: Option<Duration> },
+    /// Like [`SearchMode::Anytime`], but collect up to `count` distinct solutions, keeping the
+    /// shortest ones, and ignore the solutions with more than `max_movements`.
+    ///
+    /// These are not all the solutions up to that length: each position is only explored through
+    /// the first path that reached it at its smallest depth, so the solutions that go through the
+    /// same position by another path are not found. Only the shortest solution is proven optimal.
+    Multiple {
+        count: usize,
+        max_movements: Option<u16>,
+        time_limit: Option<Duration>,
+    },
+}
+
+/// How the progress of the search is reported on the standard output
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Report {
+    /// A human-readable line for each [`SearchEvent`]
+    Text,
+    /// A JSON object for each [`SearchEvent`], on its own line
+    JsonLines,
+    Silent,
+}
+
+/// The counters of a search, or of one of its threads
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SearchStats {
+    /// The positions whose neighbours were generated
+    pub iterations: usize,
+    pub seen_positions: usize,
+    /// The nodes left in the queues
+    pub queued: usize,
+    /// The neighbours that were not enqueued because they were already seen
+    pub rejections: usize,
+    /// The estimated number of rejections that were false positives, when the seen positions
+    /// are not exact
+    pub false_rejections: Option<usize>,
+    pub false_positive_rate: Option<f64>,
+    pub pruned: PruningStats,
+    /// The nodes taken by a thread from the queue of another one
+    pub steals: usize,
+    pub num_solutions: usize,
+    pub timed_out: bool,
+    /// The search stopped at [`SolverBuilder::max_seen_positions()`]
+    pub seen_limit_reached: bool,
+    pub elapsed_seconds: f64,
+}
+
+/// What happens during a search, as reported by [`Report`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SearchEvent {
+    /// A solution was kept, see [`Solver::solve_many()`]
+    SolutionFound {
+        num_movements: usize,
+        elapsed_seconds: f64,
+    },
+    /// The first positions were explored by a single thread, and the rest of the search is split
+    /// into the threads
+    WarmUpFinished {
+        stats: SearchStats,
+    },
+    /// A thread found a solution, with its own counters
+    ThreadSolved {
+        thread: usize,
+        stats: SearchStats,
+    },
+    /// Emitted periodically, see [`SolverBuilder::progress_interval()`]
+    Progress {
+        stats: SearchStats,
+        /// Since the previous progress event
+        positions_per_second: f64,
+        /// The nodes in the queue of each thread, or in the single queue during the warm up
+        queue_sizes: Vec<usize>,
+        /// The highest score of the positions explored so far
+        best_score: u8,
+        /// The resident memory of the process, only known on Linux
+        memory_bytes: Option<usize>,
+    },
+    Finished {
+        stats: SearchStats,
+    },
+}
+
+/// Build a [`Solver`] with custom parameters
+#[derive(Debug, Clone)]
+pub struct SolverBuilder {
+    warm_up: usize,
+    num_threads: usize,
+    mode: SearchMode,
+    seen_storage: SeenStorage,
+    symmetries: SymmetryGroup,
+    report: Report,
+    progress_interval: Duration,
+    trace_file: Option<PathBuf>,
+    max_seen_positions: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Solver {
+    warm_up: usize,
+    num_threads: usize,
+    mode: SearchMode,
+    seen_storage: SeenStorage,
+    symmetries: SymmetryGroup,
+    report: Report,
+    progress_interval: Duration,
+    trace_file: Option<PathBuf>,
+    max_seen_positions: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Solution {
+    movements: Vec<Movement>,
+    is_optimal: bool,
+}
+
+/// The counters of an explorer, as last published for the progress events
+#[derive(Debug, Clone, Copy, Default)]
+struct Counters {
+    iterations: usize,
+    rejections: usize,
+    pruned: PruningStats,
+    steals: usize,
+    best_score: u8,
+}
+
+/// The state of the periodic progress events
+#[derive(Debug)]
+struct Progress {
+    /// The counters of the main explorer, then of each thread
+    counters: Vec<Counters>,
+    last: Instant,
+    last_iterations: usize,
 }
 
-/// Maximize score, then minimize depth, then minimize index
+/// A visited node, packed as the index of its parent in the high bits and its change in the low
+/// bits. The position is not stored: it's replayed from the initial position when a path is
+/// reconstructed.
+#[derive(Debug, Clone, Copy)]
+struct VisitedNode(u64);
+
+/// A node waiting to be explored. The score, depth and index are packed so that comparing the keys
+/// maximizes score, then minimizes depth, then maximizes index
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 struct Enqueued {
-    score: u8,
+    key: u64,
+    position: Position,
+}
+
+/// A neighbour waiting to be checked against the seen positions with the others of its batch, see
+/// [`SeenSet::batch_size()`]
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    parent_index: u64,
+    movement: Movement,
     depth: u16,
-    index: u32,
+}
+
+/// The nodes that were visited but not enqueued yet, so that their positions are scored together
+#[derive(Debug, Default)]
+struct Unscored {
+    positions: Vec<Position>,
+    /// The depth and index of each node
+    nodes: Vec<(u16, u64)>,
+    scores: Vec<u8>,
 }
 
 #[derive(Debug)]
 struct MainExplorer {
     iterations: usize,
-    seen_positions: PrefixSet,
-    visits: Vec<VisitedPosition>,
+    initial_position: Position,
+    seen_positions: Box<dyn SeenSet>,
+    /// The [`SeenSet::batch_size()`] of the seen positions
+    batch_size: usize,
+    /// The neighbours that were not checked against the seen positions yet
+    pending: Vec<Pending>,
+    unscored: Unscored,
+    /// The positions are inserted in the seen positions in their canonical form, so that only one
+    /// of the equivalent positions is explored
+    symmetries: SymmetryGroup,
+    visits: Vec<VisitedNode>,
     queue: BinaryHeap<Enqueued>,
-    is_solved: AtomicCell<bool>,
-    solution: Mutex<Option<Vec<Movement>>>,
+    /// The frontier of each [`ThreadExplorer`]. Each thread works on its own queue, but can steal
+    /// the best nodes from the others
+    thread_queues: Vec<Mutex<BinaryHeap<Enqueued>>>,
+    /// The visits of each [`ThreadExplorer`]. They are shared because a stolen node can reference
+    /// a parent that was visited by another thread
+    thread_visits: Vec<Mutex<Vec<VisitedNode>>>,
+    /// The number of [`ThreadExplorer`]s that may still enqueue nodes, because they are expanding
+    /// one or looking for one. A thread only exits once all the queues are empty and no other
+    /// thread is active.
+    active_threads: AtomicCell<usize>,
+    /// The number of [`ThreadExplorer`]s parked on `idle_condvar` until a node is enqueued or no
+    /// thread is active anymore
+    idle_threads: AtomicCell<usize>,
+    idle_lock: Mutex<()>,
+    idle_condvar: Condvar,
+    mode: SearchMode,
+    start: Instant,
+    deadline: Option<Instant>,
+    is_finished: AtomicCell<bool>,
+    timed_out: AtomicCell<bool>,
+    max_seen_positions: Option<usize>,
+    /// The positions inserted by all the explorers, only counted with `max_seen_positions`, since
+    /// counting the seen positions themselves takes a lock per shard
+    num_seen_positions: AtomicCell<usize>,
+    seen_limit_reached: AtomicCell<bool>,
+    /// Nodes at this depth or deeper cannot improve the collected solutions
+    depth_limit: AtomicCell<u16>,
+    max_solutions: usize,
+    /// The distinct solutions found so far, from the shortest to the longest
+    solutions: Mutex<Vec<Vec<Movement>>>,
     rejections: usize,
+    pruned: PruningStats,
+    best_score: u8,
+    report: Report,
+    progress_interval: Duration,
+    progress: Mutex<Progress>,
+    trace: Option<TraceWriter>,
+    /// The records of the warm up that were not written to the trace yet
+    trace_records: Vec<TraceRecord>,
+    /// Why the search was stopped early, if it failed
+    error: Mutex<Option<anyhow::Error>>,
 }
 
 #[derive(Debug)]
 struct ThreadExplorer<'a> {
+    id: usize,
     iterations: usize,
     main: &'a MainExplorer,
-    visits: Vec<VisitedPosition>,
-    queue: BinaryHeap<Enqueued>,
+    num_visits: usize,
     rejections: usize,
+    pruned: PruningStats,
+    steals: usize,
+    best_score: u8,
+    next_rebalance: usize,
+    /// Whether this thread is counted in [`MainExplorer::active_threads`]
+    is_active: bool,
+    pending: Vec<Pending>,
+    unscored: Unscored,
+    trace_records: Vec<TraceRecord>,
 }
 
+/// How many iterations a [`ThreadExplorer`] does before checking if another thread has better
+/// nodes to work on
+const REBALANCE_INTERVAL: usize = 32;
+
+/// The maximum number of nodes taken from another thread in a single steal
+const STEAL_BATCH: usize = 64;
+
+const INDEX_BITS: u32 = 40;
+
+/// Nodes are numbered from 0, and the last index marks the initial node as having no parent
+const MAX_NODES: u64 = (1 << INDEX_BITS) - 1;
+
+const CHANGE_BITS: u32 = 16;
+
+/// How many iterations an explorer does before publishing its counters for the progress events.
+/// It's a power of two, so that it's cheap to check.
+const PROGRESS_CHECK_INTERVAL: usize = 1 << 12;
+
+/// How many records an explorer gathers before writing them to the trace
+const TRACE_BLOCK: usize = 1 << 12;
+
 trait Explorer {
-    fn insert_position(&mut self, position: Position) -> bool;
-    fn next_index(&self) -> u32;
-    fn push_visit(&mut self, visit: VisitedPosition);
-    fn queue_mut(&mut self) -> &mut BinaryHeap<Enqueued>;
-    fn get_visit(&self, index: u32) -> VisitedPosition;
-    fn is_solved(&self) -> bool;
-    fn set_solution(&self, solution: Vec<Movement>);
+    fn insert_position(&mut self, position: Position, depth: u16) -> bool;
+    /// Returns whether the position of each neighbour was inserted
+    fn insert_positions(&self, pending: &[Pending]) -> Vec<bool>;
+    fn batch_size(&self) -> usize;
+    fn pending_mut(&mut self) -> &mut Vec<Pending>;
+    fn unscored_mut(&mut self) -> &mut Unscored;
+    fn next_index(&self) -> u64;
+    fn push_visit(&mut self, visit: VisitedNode);
+    fn push_queue(&mut self, enqueued: Enqueued);
+    fn pop_queue(&mut self) -> Option<Enqueued>;
+    fn get_visit(&self, index: u64) -> VisitedNode;
+    fn initial_position(&self) -> Position;
+    fn should_stop(&self) -> bool;
+    /// Whether the search is over, without checking the deadline, which is cheap enough to be
+    /// done for every neighbour
+    fn is_finished(&self) -> bool;
+    /// Stop the search because of an error
+    fn fail(&self, error: anyhow::Error);
+    fn depth_limit(&self) -> u16;
+    /// Record a solution, returning whether it was kept
+    fn offer_solution(&self, solution: Vec<Movement>) -> bool;
+    fn report_solution(&self) {}
+    /// Count the positions that were inserted in the seen positions, stopping the search if
+    /// there are too many
+    fn count_seen_positions(&self, num_positions: usize);
     fn iterations_mut(&mut self) -> &mut usize;
     fn rejections_mut(&mut self) -> &mut usize;
+    fn pruned_mut(&mut self) -> &mut PruningStats;
+    fn best_score_mut(&mut self) -> &mut u8;
+    /// Publish the counters of this explorer, and emit a progress event if it's time
+    fn report_progress(&self);
+    fn is_tracing(&self) -> bool;
+    fn trace(&mut self, record: TraceRecord);
+
+    /// Whether a node at the given depth could lead to a solution that would be kept
+    fn can_improve(&self, depth: u16) -> bool {
+        depth < self.depth_limit()
+    }
+
+    /// Returns whether the position of `movement` was enqueued, or buffered until its batch is
+    /// checked against the seen positions
+    fn enqueue(&mut self, parent: Enqueued, movement: Movement) -> bool {
+        let depth = parent.depth() + 1;
+        if !self.can_improve(depth) {
+            return false;
+        }
 
-    fn enqueue(&mut self, parent: Enqueued, movement: Movement) {
-        if self.insert_position(movement.position()) {
-            let next_index = self.next_index();
-            self.push_visit(VisitedPosition {
+        // Solutions are detected here instead of when they are popped, because the solved
+        // position would be rejected by the seen positions when reached by another path
+        if movement.position() == Position::solved() {
+            let mut movements = self.path_to(parent.index());
+            movements.push(movement);
+            if self.offer_solution(movements) {
+                self.report_solution();
+            }
+            return false;
+        }
+
+        // The rotations are not generated as neighbours, so a solution that ends with one is
+        // completed here
+        if let Some(rotation) = movement.position().solving_rotation() {
+            if self.can_improve(depth + 1) {
+                let mut movements = self.path_to(parent.index());
+                movements.push(movement);
+                movements.push(Movement::new(rotation, Position::solved()));
+                if self.offer_solution(movements) {
+                    self.report_solution();
+                }
+            }
+        }
+
+        if self.batch_size() > 1 {
+            self.pending_mut().push(Pending {
+                parent_index: parent.index(),
                 movement,
-                prev_index: Some(parent.index),
-            });
-            self.queue_mut().push(Enqueued {
-                score: movement.position().score(),
-                depth: parent.depth + 1,
-                index: next_index,
+                depth,
             });
+            if self.pending_mut().len() >= self.batch_size() {
+                self.flush_pending();
+            }
+            return true;
+        }
+
+        if self.insert_position(movement.position(), depth) {
+            self.push_node(parent.index(), movement, depth)
         } else {
             *self.rejections_mut() += 1;
+            false
+        }
+    }
+
+    /// Check the buffered neighbours against the seen positions at once, and enqueue the new ones
+    fn flush_pending(&mut self) {
+        let mut pending = std::mem::take(self.pending_mut());
+        // The collected solutions may have improved since they were buffered
+        pending.retain(|pending| self.can_improve(pending.depth));
+
+        let inserted = self.insert_positions(&pending);
+        for (pending, inserted) in pending.iter().zip(inserted) {
+            if !inserted {
+                *self.rejections_mut() += 1;
+            } else if !self.push_node(pending.parent_index, pending.movement, pending.depth) {
+                break;
+            }
         }
+
+        // Keep the allocation for the next batch
+        pending.clear();
+        *self.pending_mut() = pending;
+        self.enqueue_unscored();
     }
 
-    fn pop(&mut self) -> Option<(Enqueued, VisitedPosition)> {
-        if self.is_solved() {
-            return None;
+    /// Visit a position that was inserted in the seen positions, returning false if the nodes
+    /// can't be indexed anymore. It's enqueued by [`Explorer::enqueue_unscored()`].
+    fn push_node(&mut self, parent_index: u64, movement: Movement, depth: u16) -> bool {
+        let next_index = self.next_index();
+        if next_index >= MAX_NODES {
+            self.fail(anyhow!(
+                "the search reached {} nodes, which is more than the node store can index",
+                format_big_int(next_index as usize)
+            ));
+            return false;
         }
 
-        self.queue_mut().pop().map(|enqueued| {
-            *self.iterations_mut() += 1;
-            (enqueued, self.get_visit(enqueued.index))
-        })
+        self.push_visit(VisitedNode::new(Some(parent_index), movement.change()));
+        let unscored = self.unscored_mut();
+        unscored.positions.push(movement.position());
+        unscored.nodes.push((depth, next_index));
+        true
     }
 
-    fn mark_solved(&mut self, mut solution: VisitedPosition) {
-        let mut movements = vec![];
-        while let Some(prev_index) = solution.prev_index {
-            movements.push(solution.movement);
-            solution = self.get_visit(prev_index);
+    /// Score the visited nodes together, and enqueue them. The score is only computed for the
+    /// positions that were not seen yet.
+    fn enqueue_unscored(&mut self) {
+        let mut unscored = std::mem::take(self.unscored_mut());
+        Position::score_batch(&unscored.positions, &mut unscored.scores);
+        for ((&position, &(depth, index)), &score) in unscored
+            .positions
+            .iter()
+            .zip(&unscored.nodes)
+            .zip(&unscored.scores)
+        {
+            self.push_queue(Enqueued::new(position, score, depth, index));
         }
-        movements.push(solution.movement);
-        movements.reverse();
-        self.set_solution(movements);
+
+        self.count_seen_positions(unscored.nodes.len());
+
+        // Keep the allocations for the next nodes
+        unscored.positions.clear();
+        unscored.nodes.clear();
+        *self.unscored_mut() = unscored;
+    }
+
+    /// Enqueue the neighbours of the node. Only the initial node, at index 0, was not reached by a
+    /// movement
+    fn expand(&mut self, enqueued: Enqueued, neighbours: &mut NeighboursStack) {
+        let after_movement = enqueued.index() != 0;
+        let (mut generated, mut num_enqueued) = (0u16, 0u16);
+        let _ = enqueued
+            .position
+            .visit_neighbours(neighbours, after_movement, |new_movement| {
+                generated = generated.saturating_add(1);
+                if self.enqueue(enqueued, new_movement) {
+                    num_enqueued = num_enqueued.saturating_add(1);
+                }
+                self.visit_control(enqueued)
+            });
+        *self.pruned_mut() += neighbours.take_pruned();
+        self.enqueue_unscored();
+
+        if self.is_tracing() {
+            let record = TraceRecord {
+                position: enqueued.position,
+                score: enqueued.score(),
+                depth: enqueued.depth(),
+                index: enqueued.index(),
+                parent: self.get_visit(enqueued.index()).parent(),
+                generated,
+                enqueued: num_enqueued,
+            };
+            self.trace(record);
+        }
+    }
+
+    /// Stop generating the neighbours of `parent` once none of them can be kept, for example
+    /// because a solution was found meanwhile
+    fn visit_control(&self, parent: Enqueued) -> ControlFlow<()> {
+        if self.is_finished() || !self.can_improve(parent.depth() + 1) {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+
+    fn pop(&mut self) -> Option<Enqueued> {
+        loop {
+            if self.should_stop() {
+                return None;
+            }
+
+            let enqueued = self.pop_queue()?;
+
+            // The collected solutions may have improved since this node was enqueued, so that its
+            // children are no longer interesting
+            if self.can_improve(enqueued.depth() + 1) {
+                let best_score = self.best_score_mut();
+                *best_score = (*best_score).max(enqueued.score());
+
+                let iterations = self.iterations_mut();
+                *iterations += 1;
+                if *iterations & (PROGRESS_CHECK_INTERVAL - 1) == 0 {
+                    self.report_progress();
+                }
+                return Some(enqueued);
+            }
+        }
+    }
+
+    /// Reconstruct the movements from the initial position to the node at the given index
+    fn path_to(&self, index: u64) -> Vec<Movement> {
+        let mut changes = vec![];
+        let mut visit = self.get_visit(index);
+        while let Some(parent) = visit.parent() {
+            changes.push(visit.change());
+            visit = self.get_visit(parent);
+        }
+
+        let mut movements = vec![Movement::initial_movement(self.initial_position())];
+        for change in changes.into_iter().rev() {
+            let position = movements[movements.len() - 1].position();
+            let next = position
+                .apply(change)
+                .expect("the stored changes were possible when they were visited");
+            movements.push(Movement::new(change, next));
+        }
+        movements
     }
 }
 
 impl MainExplorer {
-    fn new(initial_position: Position) -> Self {
-        let seen_positions = PrefixSet::new();
-        let mut all_movements = Vec::new();
+    fn new(
+        initial_position: Position,
+        mode: SearchMode,
+        seen_positions: Box<dyn SeenSet>,
+        symmetries: SymmetryGroup,
+        report: Report,
+        progress_interval: Duration,
+        trace: Option<TraceWriter>,
+    ) -> Result<Self> {
+        let start = Instant::now();
+        let (time_limit, max_solutions, depth_limit) = match mode {
+            SearchMode::FirstSolution => (None, 1, u16::MAX),
+            SearchMode::Anytime { time_limit } => (time_limit, 1, u16::MAX),
+            SearchMode::Multiple {
+                count,
+                max_movements,
+                time_limit,
+            } => (
+                time_limit,
+                count.max(1),
+                max_movements.map_or(u16::MAX, |max| max.saturating_add(1)),
+            ),
+        };
+        let deadline = time_limit.map(|limit| start + limit);
         let mut queue = BinaryHeap::new();
 
         let initial_movement = Movement::initial_movement(initial_position);
-        all_movements.push(VisitedPosition {
-            prev_index: None,
-            movement: initial_movement,
-        });
-        seen_positions.insert(initial_position.as_bytes());
-        queue.push(Enqueued {
-            score: initial_position.score(),
-            depth: 0,
-            index: 0,
-        });
+        let visits = vec![VisitedNode::new(None, initial_movement.change())];
+        seen_positions.insert(symmetries.canonical(initial_position).as_bytes(), 0)?;
+        queue.push(Enqueued::new(
+            initial_position,
+            initial_position.score(),
+            0,
+            0,
+        ));
 
-        MainExplorer {
+        Ok(MainExplorer {
             iterations: 0,
+            initial_position,
+            batch_size: seen_positions.batch_size(),
+            pending: vec![],
+            unscored: Unscored::default(),
             seen_positions,
-            visits: all_movements,
+            symmetries,
+            visits,
             queue,
-            is_solved: AtomicCell::new(false),
-            solution: Mutex::new(None),
+            thread_queues: vec![],
+            thread_visits: vec![],
+            active_threads: AtomicCell::new(0),
+            idle_threads: AtomicCell::new(0),
+            idle_lock: Mutex::new(()),
+            idle_condvar: Condvar::new(),
+            mode,
+            start,
+            deadline,
+            is_finished: AtomicCell::new(false),
+            timed_out: AtomicCell::new(false),
+            max_seen_positions: None,
+            num_seen_positions: AtomicCell::new(1),
+            seen_limit_reached: AtomicCell::new(false),
+            depth_limit: AtomicCell::new(depth_limit),
+            max_solutions,
+            solutions: Mutex::new(vec![]),
             rejections: 0,
-        }
+            pruned: PruningStats::default(),
+            best_score: 0,
+            report,
+            progress_interval,
+            progress: Mutex::new(Progress {
+                counters: vec![Counters::default()],
+                last: start,
+                last_iterations: 0,
+            }),
+            trace,
+            trace_records: vec![],
+            error: Mutex::new(None),
+        })
     }
 
-    fn explode(&mut self, num: usize) -> Vec<ThreadExplorer> {
+    fn explode(&mut self, num: usize) -> Vec<ThreadExplorer<'_>> {
         // Round-robin the queue
         let mut thread_queues = (0..num).map(|_| BinaryHeap::new()).collect_vec();
         let mut i = 0;
@@ -131,52 +624,360 @@ impl MainExplorer {
             i = (i + 1) % thread_queues.len();
         }
 
+        self.thread_queues = thread_queues.into_iter().map(Mutex::new).collect();
+        self.thread_visits = (0..num).map(|_| Mutex::new(vec![])).collect();
+
+        let mut counters = vec![Counters::default(); num + 1];
+        counters[0] = self.counters();
+        self.progress.lock().counters = counters;
+
         let main = &*self;
-        thread_queues
-            .into_iter()
-            .map(move |queue| ThreadExplorer {
+        (0..num)
+            .map(move |id| ThreadExplorer {
+                id,
                 iterations: 0,
                 main,
-                visits: vec![],
-                queue,
+                num_visits: 0,
                 rejections: 0,
+                pruned: PruningStats::default(),
+                steals: 0,
+                best_score: 0,
+                next_rebalance: 0,
+                is_active: false,
+                pending: vec![],
+                unscored: Unscored::default(),
+                trace_records: vec![],
             })
             .collect()
     }
 
-    fn solution(&self) -> Option<Vec<Movement>> {
-        self.solution.lock().clone()
+    fn solutions(&self) -> Result<Vec<Solution>> {
+        if let Some(error) = self.error.lock().take() {
+            return Err(error);
+        }
+
+        // Only the shortest solution is proven to be optimal when the frontier is exhausted. The
+        // others may have missed shorter alternatives that shared a position with a kept path
+        let is_optimal = match self.mode {
+            SearchMode::FirstSolution => false,
+            SearchMode::Anytime { .. } | SearchMode::Multiple { .. } => {
+                !self.timed_out.load()
+                    && !self.seen_limit_reached.load()
+                    && self.seen_positions.is_exact()
+            }
+        };
+
+        Ok(self
+            .solutions
+            .lock()
+            .iter()
+            .enumerate()
+            .map(|(i, movements)| Solution {
+                movements: movements.clone(),
+                is_optimal: is_optimal && i == 0,
+            })
+            .collect())
+    }
+
+    fn counters(&self) -> Counters {
+        Counters {
+            iterations: self.iterations,
+            rejections: self.rejections,
+            pruned: self.pruned,
+            steals: 0,
+            best_score: self.best_score,
+        }
+    }
+
+    fn stats(&self, counters: Counters, queued: usize) -> SearchStats {
+        SearchStats {
+            iterations: counters.iterations,
+            seen_positions: self.seen_positions.len(),
+            queued,
+            rejections: counters.rejections,
+            false_rejections: self.seen_positions.false_rejections(),
+            false_positive_rate: self.seen_positions.false_positive_rate(),
+            pruned: counters.pruned,
+            steals: counters.steals,
+            num_solutions: self.solutions.lock().len(),
+            timed_out: self.timed_out.load(),
+            seen_limit_reached: self.seen_limit_reached.load(),
+            elapsed_seconds: self.start.elapsed().as_secs_f64(),
+        }
+    }
+
+    fn queues_are_empty(&self) -> bool {
+        self.thread_queues
+            .iter()
+            .all(|queue| queue.lock().is_empty())
+    }
+
+    /// Park an idle thread until a node is enqueued, or until no thread is active and none can
+    /// enqueue anymore
+    fn wait_for_nodes(&self) {
+        let mut guard = self.idle_lock.lock();
+        // Counted before checking the queues, so that a thread that enqueues in between wakes
+        // this one up
+        self.idle_threads.fetch_add(1);
+        if self.queues_are_empty() && self.active_threads.load() > 0 && !self.is_finished.load() {
+            self.idle_condvar.wait(&mut guard);
+        }
+        self.idle_threads.fetch_sub(1);
+    }
+
+    fn add_seen_positions(&self, num_positions: usize) {
+        let max_seen_positions = match self.max_seen_positions {
+            Some(max_seen_positions) => max_seen_positions,
+            None => return,
+        };
+        if self.num_seen_positions.fetch_add(num_positions) + num_positions >= max_seen_positions {
+            self.seen_limit_reached.store(true);
+            self.is_finished.store(true);
+        }
+    }
+
+    fn wake_idle_thread(&self) {
+        if self.idle_threads.load() > 0 {
+            let _guard = self.idle_lock.lock();
+            self.idle_condvar.notify_one();
+        }
+    }
+
+    /// Publish the counters of an explorer, where 0 is the main explorer and the threads follow,
+    /// and emit a progress event if the interval has elapsed since the previous one
+    fn update_progress(&self, slot: usize, counters: Counters) {
+        let mut progress = self.progress.lock();
+        progress.counters[slot] = counters;
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(progress.last);
+        if elapsed < self.progress_interval {
+            return;
+        }
+        let total = Counters::total(&progress.counters);
+        let positions_per_second =
+            (total.iterations - progress.last_iterations) as f64 / elapsed.as_secs_f64();
+        progress.last = now;
+        progress.last_iterations = total.iterations;
+        drop(progress);
+
+        let queue_sizes = if self.thread_queues.is_empty() {
+            vec![self.queue.len()]
+        } else {
+            self.thread_queues
+                .iter()
+                .map(|queue| queue.lock().len())
+                .collect()
+        };
+        self.emit(SearchEvent::Progress {
+            stats: self.stats(total, queue_sizes.iter().sum()),
+            positions_per_second,
+            queue_sizes,
+            best_score: total.best_score,
+            memory_bytes: resident_memory(),
+        });
+    }
+
+    /// Write the records to the trace, stopping the search if it fails
+    fn write_trace(&self, records: &mut Vec<TraceRecord>) {
+        if let Some(trace) = &self.trace {
+            if let Err(error) = trace.write(records) {
+                self.fail(error);
+            }
+        }
+        records.clear();
+    }
+
+    /// Write the records of the warm up, and flush the trace
+    fn finish_trace(&mut self) -> Result<()> {
+        let mut records = std::mem::take(&mut self.trace_records);
+        self.write_trace(&mut records);
+        match &self.trace {
+            Some(trace) => trace.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Log the event, and print it on the standard output according to [`Report`]
+    fn emit(&self, event: SearchEvent) {
+        let level = match event {
+            SearchEvent::ThreadSolved { .. } => Level::Debug,
+            _ => Level::Info,
+        };
+        log::log!(level, event:serde = event; "{}", event);
+
+        match self.report {
+            Report::Text => println!("{}", event),
+            Report::JsonLines => println!(
+                "{}",
+                serde_json::to_string(&event).expect("the events can be serialized")
+            ),
+            Report::Silent => {}
+        }
+    }
+
+    /// Whether a position reached by a shorter path is inserted again
+    fn replace_deeper(&self) -> bool {
+        match self.mode {
+            SearchMode::FirstSolution => false,
+            // A position reached by a shorter path must be explored again, otherwise the
+            // exhaustion of the frontier would not prove that the best solution is optimal
+            SearchMode::Anytime { .. } | SearchMode::Multiple { .. } => true,
+        }
+    }
+
+    /// Returns whether the position was inserted. If the seen positions can't be accessed, the
+    /// search fails and nothing is inserted.
+    fn insert_position(&self, position: Position, depth: u16) -> bool {
+        let key = self.symmetries.canonical(position).as_bytes();
+        let inserted = if self.replace_deeper() {
+            self.seen_positions.insert_if_shallower(key, depth)
+        } else {
+            self.seen_positions.insert(key, depth)
+        };
+        inserted.unwrap_or_else(|error| {
+            self.fail(error.context("failed to access the seen positions"));
+            false
+        })
+    }
+
+    /// Like [`MainExplorer::insert_position()`], for a batch of neighbours
+    fn insert_positions(&self, pending: &[Pending]) -> Vec<bool> {
+        let entries = pending
+            .iter()
+            .map(|pending| {
+                let key = self.symmetries.canonical(pending.movement.position());
+                (key.as_bytes(), pending.depth)
+            })
+            .collect_vec();
+        self.seen_positions
+            .insert_batch(&entries, self.replace_deeper())
+            .unwrap_or_else(|error| {
+                self.fail(error.context("failed to access the seen positions"));
+                vec![false; entries.len()]
+            })
     }
 }
 
 impl Explorer for MainExplorer {
-    fn insert_position(&mut self, position: Position) -> bool {
-        self.seen_positions.insert(position.as_bytes())
+    fn insert_position(&mut self, position: Position, depth: u16) -> bool {
+        MainExplorer::insert_position(self, position, depth)
+    }
+
+    fn insert_positions(&self, pending: &[Pending]) -> Vec<bool> {
+        MainExplorer::insert_positions(self, pending)
     }
 
-    fn next_index(&self) -> u32 {
-        self.visits.len() as u32
+    fn batch_size(&self) -> usize {
+        self.batch_size
     }
 
-    fn push_visit(&mut self, visit: VisitedPosition) {
+    fn pending_mut(&mut self) -> &mut Vec<Pending> {
+        &mut self.pending
+    }
+
+    fn unscored_mut(&mut self) -> &mut Unscored {
+        &mut self.unscored
+    }
+
+    fn next_index(&self) -> u64 {
+        self.visits.len() as u64
+    }
+
+    fn push_visit(&mut self, visit: VisitedNode) {
         self.visits.push(visit)
     }
 
-    fn queue_mut(&mut self) -> &mut BinaryHeap<Enqueued> {
-        &mut self.queue
+    fn push_queue(&mut self, enqueued: Enqueued) {
+        self.queue.push(enqueued);
+    }
+
+    /// The buffered neighbours are checked once the queue is empty
+    fn pop_queue(&mut self) -> Option<Enqueued> {
+        if self.queue.is_empty() {
+            self.flush_pending();
+        }
+        self.queue.pop()
     }
 
-    fn get_visit(&self, index: u32) -> VisitedPosition {
+    fn get_visit(&self, index: u64) -> VisitedNode {
         self.visits[index as usize]
     }
 
-    fn is_solved(&self) -> bool {
-        self.is_solved.load()
+    fn initial_position(&self) -> Position {
+        self.initial_position
     }
 
-    fn set_solution(&self, solution: Vec<Movement>) {
-        self.is_solved.store(true);
-        *self.solution.lock() = Some(solution);
+    fn should_stop(&self) -> bool {
+        if self.is_finished.load() {
+            return true;
+        }
+
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.timed_out.store(true);
+                self.is_finished.store(true);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.is_finished.load()
+    }
+
+    fn fail(&self, error: anyhow::Error) {
+        let mut current = self.error.lock();
+        if current.is_none() {
+            *current = Some(error);
+        }
+        self.is_finished.store(true);
+    }
+
+    fn depth_limit(&self) -> u16 {
+        self.depth_limit.load()
+    }
+
+    fn offer_solution(&self, solution: Vec<Movement>) -> bool {
+        // The first movement is the initial position
+        let depth = (solution.len() - 1) as u16;
+
+        let mut solutions = self.solutions.lock();
+        if depth >= self.depth_limit.load() {
+            return false;
+        }
+
+        let is_duplicated = solutions.iter().any(|other| {
+            other.len() == solution.len()
+                && other
+                    .iter()
+                    .zip(&solution)
+                    .all(|(a, b)| a.change() == b.change())
+        });
+        if is_duplicated {
+            return false;
+        }
+
+        self.emit(SearchEvent::SolutionFound {
+            num_movements: depth as usize,
+            elapsed_seconds: self.start.elapsed().as_secs_f64(),
+        });
+        let insert_at = solutions.partition_point(|other| other.len() <= solution.len());
+        solutions.insert(insert_at, solution);
+        solutions.truncate(self.max_solutions);
+
+        // Once enough solutions are known, only shorter ones are interesting
+        if solutions.len() == self.max_solutions {
+            let longest = (solutions[solutions.len() - 1].len() - 1) as u16;
+            self.depth_limit.store(longest);
+        }
+
+        if self.mode == SearchMode::FirstSolution {
+            self.is_finished.store(true);
+        }
+
+        true
     }
 
     fn iterations_mut(&mut self) -> &mut usize {
@@ -186,14 +987,96 @@ impl Explorer for MainExplorer {
     fn rejections_mut(&mut self) -> &mut usize {
         &mut self.rejections
     }
+
+    fn pruned_mut(&mut self) -> &mut PruningStats {
+        &mut self.pruned
+    }
+
+    fn best_score_mut(&mut self) -> &mut u8 {
+        &mut self.best_score
+    }
+
+    fn count_seen_positions(&self, num_positions: usize) {
+        self.add_seen_positions(num_positions);
+    }
+
+    fn report_progress(&self) {
+        self.update_progress(0, self.counters());
+    }
+
+    fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    fn trace(&mut self, record: TraceRecord) {
+        self.trace_records.push(record);
+        if self.trace_records.len() >= TRACE_BLOCK {
+            let mut records = std::mem::take(&mut self.trace_records);
+            self.write_trace(&mut records);
+            self.trace_records = records;
+        }
+    }
+}
+
+impl Counters {
+    fn total(counters: &[Counters]) -> Counters {
+        let mut total = Counters::default();
+        for counters in counters {
+            total.iterations += counters.iterations;
+            total.rejections += counters.rejections;
+            total.pruned += counters.pruned;
+            total.steals += counters.steals;
+            total.best_score = total.best_score.max(counters.best_score);
+        }
+        total
+    }
+}
+
+impl VisitedNode {
+    fn new(parent: Option<u64>, change: Change) -> Self {
+        let parent = parent.unwrap_or(MAX_NODES);
+        VisitedNode(parent << CHANGE_BITS | change.as_bytes() as u64)
+    }
+
+    fn parent(self) -> Option<u64> {
+        let parent = self.0 >> CHANGE_BITS;
+        if parent == MAX_NODES {
+            None
+        } else {
+            Some(parent)
+        }
+    }
+
+    fn change(self) -> Change {
+        Change::from_bytes(self.0 as u16)
+    }
+}
+
+impl Enqueued {
+    fn new(position: Position, score: u8, depth: u16, index: u64) -> Self {
+        let key =
+            (score as u64) << (u64::BITS - 8) | ((u16::MAX - depth) as u64) << INDEX_BITS | index;
+        Enqueued { key, position }
+    }
+
+    fn score(self) -> u8 {
+        (self.key >> (u64::BITS - 8)) as u8
+    }
+
+    fn depth(self) -> u16 {
+        u16::MAX - (self.key >> INDEX_BITS) as u16
+    }
+
+    fn index(self) -> u64 {
+        self.key & MAX_NODES
+    }
 }
 
 impl Ord for Enqueued {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.score
-            .cmp(&other.score)
-            .then(self.depth.cmp(&other.depth).reverse())
-            .then(self.index.cmp(&other.index))
+        self.key
+            .cmp(&other.key)
+            .then(self.position.cmp(&other.position))
     }
 }
 
@@ -203,38 +1086,198 @@ impl PartialOrd for Enqueued {
     }
 }
 
+impl ThreadExplorer<'_> {
+    /// Stop counting this thread in [`MainExplorer::active_threads`]. The last active thread
+    /// wakes up all the idle ones, so that they exit
+    fn deactivate(&mut self) {
+        if !self.is_active {
+            return;
+        }
+        self.is_active = false;
+        if self.main.active_threads.fetch_sub(1) == 1 {
+            let _guard = self.main.idle_lock.lock();
+            self.main.idle_condvar.notify_all();
+        }
+    }
+
+    fn counters(&self) -> Counters {
+        Counters {
+            iterations: self.iterations,
+            rejections: self.rejections,
+            pruned: self.pruned,
+            steals: self.steals,
+            best_score: self.best_score,
+        }
+    }
+
+    fn queue_len(&self) -> usize {
+        self.main.thread_queues[self.id].lock().len()
+    }
+
+    /// Move the best nodes of the thread with the most promising queue into this one, if they have
+    /// a better score than the best node of this thread.
+    ///
+    /// Returns whether any node was stolen.
+    fn steal(&mut self) -> bool {
+        let own_queue = &self.main.thread_queues[self.id];
+        let own_best = own_queue.lock().peek().map(|enqueued| enqueued.score());
+
+        let victim = self
+            .main
+            .thread_queues
+            .iter()
+            .enumerate()
+            .filter(|&(id, _)| id != self.id)
+            .filter_map(|(id, queue)| Some((queue.lock().peek()?.score(), id)))
+            .max();
+        let victim = match victim {
+            Some((victim_best, victim)) if Some(victim_best) > own_best => victim,
+            _ => return false,
+        };
+
+        let mut stolen = Vec::with_capacity(STEAL_BATCH);
+        {
+            let mut victim_queue = self.main.thread_queues[victim].lock();
+            while stolen.len() < STEAL_BATCH {
+                match victim_queue.peek() {
+                    Some(top) if Some(top.score()) > own_best => {
+                        stolen.extend(victim_queue.pop());
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        self.steals += stolen.len();
+        let stole_any = !stolen.is_empty();
+        own_queue.lock().extend(stolen);
+        stole_any
+    }
+}
+
 impl Explorer for ThreadExplorer<'_> {
-    fn insert_position(&mut self, position: Position) -> bool {
-        self.main.seen_positions.insert(position.as_bytes())
+    fn insert_position(&mut self, position: Position, depth: u16) -> bool {
+        self.main.insert_position(position, depth)
+    }
+
+    fn insert_positions(&self, pending: &[Pending]) -> Vec<bool> {
+        self.main.insert_positions(pending)
     }
 
-    fn next_index(&self) -> u32 {
-        (self.main.visits.len() + self.visits.len()) as u32
+    fn batch_size(&self) -> usize {
+        self.main.batch_size
     }
 
-    fn push_visit(&mut self, visit: VisitedPosition) {
-        self.visits.push(visit);
+    fn pending_mut(&mut self) -> &mut Vec<Pending> {
+        &mut self.pending
     }
 
-    fn queue_mut(&mut self) -> &mut BinaryHeap<Enqueued> {
-        &mut self.queue
+    fn unscored_mut(&mut self) -> &mut Unscored {
+        &mut self.unscored
     }
 
-    fn get_visit(&self, index: u32) -> VisitedPosition {
+    /// The indexes of the nodes visited by each thread are interleaved after the ones visited by
+    /// the main explorer
+    fn next_index(&self) -> u64 {
+        let num_threads = self.main.thread_visits.len();
+        (self.main.visits.len() + self.num_visits * num_threads + self.id) as u64
+    }
+
+    fn push_visit(&mut self, visit: VisitedNode) {
+        self.main.thread_visits[self.id].lock().push(visit);
+        self.num_visits += 1;
+    }
+
+    fn push_queue(&mut self, enqueued: Enqueued) {
+        self.main.thread_queues[self.id].lock().push(enqueued);
+        self.main.wake_idle_thread();
+    }
+
+    /// The thread stays active until it finds its queue, its buffered neighbours and the other
+    /// queues empty, so that it waits for the children of the nodes that the other threads are
+    /// still expanding. Meanwhile, it is parked until a node is enqueued
+    fn pop_queue(&mut self) -> Option<Enqueued> {
+        if self.iterations >= self.next_rebalance {
+            self.next_rebalance = self.iterations + REBALANCE_INTERVAL;
+            self.steal();
+        }
+
+        loop {
+            // Counted before popping, so that the others can't see an empty queue and no active
+            // thread in between
+            if !self.is_active {
+                self.main.active_threads.fetch_add(1);
+                self.is_active = true;
+            }
+            let next = self.main.thread_queues[self.id].lock().pop();
+            let next = match next {
+                Some(next) => Some(next),
+                None if self.steal() => self.main.thread_queues[self.id].lock().pop(),
+                None => None,
+            };
+            if next.is_some() {
+                return next;
+            }
+            // The buffered neighbours may refill the queue
+            if !self.pending.is_empty() {
+                self.flush_pending();
+                continue;
+            }
+
+            self.deactivate();
+            // The queues are checked before the active threads, since a thread only becomes
+            // inactive after enqueuing the children of its last node
+            if (self.main.queues_are_empty() && self.main.active_threads.load() == 0)
+                || self.should_stop()
+            {
+                return None;
+            }
+            self.main.wait_for_nodes();
+        }
+    }
+
+    fn get_visit(&self, index: u64) -> VisitedNode {
         let index = index as usize;
-        if index < self.main.visits.len() {
+        let main_visits = self.main.visits.len();
+        if index < main_visits {
             self.main.visits[index]
         } else {
-            self.visits[index - self.main.visits.len()]
+            let num_threads = self.main.thread_visits.len();
+            let thread_index = index - main_visits;
+            self.main.thread_visits[thread_index % num_threads].lock()[thread_index / num_threads]
         }
     }
 
-    fn is_solved(&self) -> bool {
-        self.main.is_solved()
+    fn initial_position(&self) -> Position {
+        self.main.initial_position
+    }
+
+    fn should_stop(&self) -> bool {
+        self.main.should_stop()
     }
 
-    fn set_solution(&self, solution: Vec<Movement>) {
-        self.main.set_solution(solution);
+    fn is_finished(&self) -> bool {
+        self.main.is_finished()
+    }
+
+    fn fail(&self, error: anyhow::Error) {
+        self.main.fail(error)
+    }
+
+    fn depth_limit(&self) -> u16 {
+        self.main.depth_limit()
+    }
+
+    fn offer_solution(&self, solution: Vec<Movement>) -> bool {
+        self.main.offer_solution(solution)
+    }
+
+    fn report_solution(&self) {
+        let stats = self.main.stats(self.counters(), self.queue_len());
+        self.main.emit(SearchEvent::ThreadSolved {
+            thread: self.id,
+            stats,
+        });
     }
 
     fn iterations_mut(&mut self) -> &mut usize {
@@ -244,73 +1287,749 @@ impl Explorer for ThreadExplorer<'_> {
     fn rejections_mut(&mut self) -> &mut usize {
         &mut self.rejections
     }
-}
 
-pub fn find_solution(
-    initial_position: Position,
-    warm_up: usize,
-    num_threads: usize,
-) -> Option<Vec<Movement>> {
-    let solved_position = Position::solved();
-    let mut explorer = MainExplorer::new(initial_position);
-    let mut neighbours = NeighboursStack::new();
+    fn pruned_mut(&mut self) -> &mut PruningStats {
+        &mut self.pruned
+    }
+
+    fn best_score_mut(&mut self) -> &mut u8 {
+        &mut self.best_score
+    }
 
-    while let Some((enqueued, next)) = explorer.pop() {
-        if next.movement.position() == solved_position {
-            explorer.mark_solved(next);
-            break;
+    fn count_seen_positions(&self, num_positions: usize) {
+        self.main.add_seen_positions(num_positions);
+    }
+
+    fn report_progress(&self) {
+        self.main.update_progress(self.id + 1, self.counters());
+    }
+
+    fn is_tracing(&self) -> bool {
+        self.main.trace.is_some()
+    }
+
+    fn trace(&mut self, record: TraceRecord) {
+        self.trace_records.push(record);
+        if self.trace_records.len() >= TRACE_BLOCK {
+            self.main.write_trace(&mut self.trace_records);
         }
+    }
+}
 
-        next.movement.position().neighbours(&mut neighbours);
-        for &new_movement in neighbours.neighbours() {
-            explorer.enqueue(enqueued, new_movement);
+impl SolverBuilder {
+    pub fn new() -> Self {
+        SolverBuilder {
+            warm_up: 100_000,
+            // There are no threads in the WebAssembly build
+            num_threads: if cfg!(target_arch = "wasm32") {
+                1
+            } else {
+                rayon::current_num_threads()
+            },
+            mode: SearchMode::FirstSolution,
+            seen_storage: SeenStorage::Memory,
+            symmetries: SymmetryGroup::Full,
+            report: Report::Silent,
+            progress_interval: Duration::from_secs(10),
+            trace_file: None,
+            max_seen_positions: None,
         }
+    }
+
+    /// How many positions are explored by a single thread before the search is split into
+    /// multiple threads. With a single thread, the warm up lasts for the whole search.
+    pub fn warm_up(mut self, warm_up: usize) -> Self {
+        self.warm_up = warm_up;
+        self
+    }
 
-        if explorer.iterations == warm_up {
-            break;
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    pub fn mode(mut self, mode: SearchMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Where the positions already reached are kept. [`SeenStorage::Disk`] allows searches that
+    /// reach more positions than what fits in memory, but is much slower.
+    pub fn seen_storage(mut self, seen_storage: SeenStorage) -> Self {
+        self.seen_storage = seen_storage;
+        self
+    }
+
+    /// The positions that are equivalent under these symmetries are explored only once. They are
+    /// at the same distance from the solved position, so the shortest solutions are still found,
+    /// but [`SearchMode::Multiple`] only collects the solutions through one of them.
+    pub fn symmetries(mut self, symmetries: SymmetryGroup) -> Self {
+        self.symmetries = symmetries;
+        self
+    }
+
+    /// How the events of the search are printed on the standard output, nowhere by default. They
+    /// are also logged, see [`SearchEvent`].
+    pub fn report(mut self, report: Report) -> Self {
+        self.report = report;
+        self
+    }
+
+    /// The minimum time between two [`SearchEvent::Progress`]. The counters are only gathered
+    /// every few thousand positions, so a short search may have no progress events.
+    pub fn progress_interval(mut self, progress_interval: Duration) -> Self {
+        self.progress_interval = progress_interval;
+        self
+    }
+
+    /// Record every expansion of the search in this file, replaced at each search, see
+    /// [`crate::trace`]. It takes [`crate::trace::RECORD_BYTES`] per expansion.
+    pub fn trace_file(mut self, trace_file: impl Into<PathBuf>) -> Self {
+        self.trace_file = Some(trace_file.into());
+        self
+    }
+
+    /// Stop the search once it has seen this many positions, like at a time limit, so that the
+    /// memory of the seen positions and of the nodes is bounded. It's checked after each
+    /// expansion, so the search can go past it by the neighbours of a position per thread.
+    pub fn max_seen_positions(mut self, max_seen_positions: usize) -> Self {
+        self.max_seen_positions = Some(max_seen_positions);
+        self
+    }
+
+    pub fn build(self) -> Solver {
+        Solver {
+            warm_up: self.warm_up,
+            num_threads: self.num_threads,
+            mode: self.mode,
+            seen_storage: self.seen_storage,
+            symmetries: self.symmetries,
+            report: self.report,
+            progress_interval: self.progress_interval,
+            trace_file: self.trace_file,
+            max_seen_positions: self.max_seen_positions,
         }
     }
+}
+
+impl Default for SolverBuilder {
+    fn default() -> Self {
+        SolverBuilder::new()
+    }
+}
+
+/// The solver of the tests: a short warm-up so that both threads take part even in small
+/// searches, and no time limit so that the solutions are proven optimal
+#[cfg(test)]
+pub fn test_solver() -> SolverBuilder {
+    Solver::builder()
+        .warm_up(10)
+        .num_threads(2)
+        .mode(SearchMode::Anytime { time_limit: None })
+}
+
+/// Solve a position that is a few changes away from the solved one, with [`test_solver()`]
+#[cfg(test)]
+pub fn solve_small(position: Position) -> Solution {
+    test_solver()
+        .build()
+        .solve(position)
+        .unwrap()
+        .expect("a solution is found without a time limit")
+}
+
+impl Solver {
+    pub fn builder() -> SolverBuilder {
+        SolverBuilder::new()
+    }
 
-    println!(
-        "Warm up finished after {} positions, {} distinct seen, {} in the queue",
-        format_big_int(explorer.iterations),
-        format_big_int(explorer.seen_positions.len()),
-        format_big_int(explorer.queue.len()),
-    );
+    /// Find a solution. In [`SearchMode::Multiple`], this returns the shortest one.
+    pub fn solve(&self, initial_position: Position) -> Result<Option<Solution>> {
+        Ok(self.solve_many(initial_position)?.into_iter().next())
+    }
 
-    let solution = explorer.solution();
-    if solution.is_some() {
-        return solution;
+    /// Find the distinct solutions, from the shortest to the longest. Only
+    /// [`SearchMode::Multiple`] can return more than one solution.
+    ///
+    /// This fails if the seen positions can't be created or if the search visits more nodes than
+    /// what can be indexed.
+    pub fn solve_many(&self, initial_position: Position) -> Result<Vec<Solution>> {
+        Ok(self.solve_many_with_stats(initial_position)?.0)
     }
 
-    let thread_explorers = explorer.explode(num_threads);
-    println!("Start threads");
-    thread_explorers
-        .into_par_iter()
-        .for_each(|mut thread_explorer| {
-            let mut neighbours = NeighboursStack::new();
+    /// Like [`Solver::solve_many()`], with the counters of the whole search
+    pub fn solve_many_with_stats(
+        &self,
+        initial_position: Position,
+    ) -> Result<(Vec<Solution>, SearchStats)> {
+        let seen_positions = self
+            .seen_storage
+            .create()
+            .context("failed to create the seen positions")?;
+        let trace = self
+            .trace_file
+            .as_deref()
+            .map(TraceWriter::create)
+            .transpose()?;
+        let mut explorer = MainExplorer::new(
+            initial_position,
+            self.mode,
+            seen_positions,
+            self.symmetries,
+            self.report,
+            self.progress_interval,
+            trace,
+        )?;
+        explorer.max_seen_positions = self.max_seen_positions;
+        let mut neighbours = NeighboursStack::new();
 
-            while let Some((enqueued, next)) = thread_explorer.pop() {
-                if next.movement.position() == solved_position {
-                    thread_explorer.mark_solved(next);
+        let initial_movement = Movement::initial_movement(initial_position);
+        if initial_position == Position::solved() {
+            explorer.offer_solution(vec![initial_movement]);
+        } else if let Some(rotation) = initial_position.solving_rotation() {
+            explorer.offer_solution(vec![
+                initial_movement,
+                Movement::new(rotation, Position::solved()),
+            ]);
+        }
+
+        while let Some(enqueued) = explorer.pop() {
+            explorer.expand(enqueued, &mut neighbours);
 
-                    println!(
-                        "Solved after {} iterations, {} distinct seen, {} in the queue, {} rejections",
-                        format_big_int(thread_explorer.iterations),
-                        format_big_int(thread_explorer.main.seen_positions.len()),
-                        format_big_int(thread_explorer.queue.len()),
-                        format_big_int(thread_explorer.rejections)
-                    );
+            if explorer.iterations == self.warm_up && self.num_threads > 1 {
+                break;
+            }
+        }
+        if !explorer.should_stop() {
+            explorer.flush_pending();
+        }
 
-                    break;
+        explorer.finish_trace()?;
+        if explorer.should_stop() || explorer.queue.is_empty() {
+            let stats = explorer.stats(explorer.counters(), explorer.queue.len());
+            explorer.emit(SearchEvent::Finished {
+                stats: stats.clone(),
+            });
+            return Ok((explorer.solutions()?, stats));
+        }
+
+        let stats = explorer.stats(explorer.counters(), explorer.queue.len());
+        explorer.emit(SearchEvent::WarmUpFinished { stats });
+
+        let main_counters = explorer.counters();
+        let thread_explorers = explorer.explode(self.num_threads);
+        let mut counters = thread_explorers
+            .into_par_iter()
+            .map(|mut thread_explorer| {
+                let mut neighbours = NeighboursStack::new();
+
+                while let Some(enqueued) = thread_explorer.pop() {
+                    thread_explorer.expand(enqueued, &mut neighbours);
                 }
+                // The search may stop while this thread is still active
+                thread_explorer.deactivate();
+                let main = thread_explorer.main;
+                main.write_trace(&mut thread_explorer.trace_records);
+
+                thread_explorer.counters()
+            })
+            .collect::<Vec<_>>();
+        counters.push(main_counters);
+        explorer.finish_trace()?;
+
+        let queued = explorer
+            .thread_queues
+            .iter()
+            .map(|queue| queue.lock().len())
+            .sum();
+        let stats = explorer.stats(Counters::total(&counters), queued);
+        explorer.emit(SearchEvent::Finished {
+            stats: stats.clone(),
+        });
+
+        Ok((explorer.solutions()?, stats))
+    }
+}
+
+impl Solution {
+    /// The movements from the initial position to the solved one. The first movement has an empty
+    /// change and holds the initial position.
+    pub fn movements(&self) -> &[Movement] {
+        &self.movements
+    }
+
+    /// Whether the search proved that no shorter solution exists
+    pub fn is_optimal(&self) -> bool {
+        self.is_optimal
+    }
+
+    /// The number of compound movements, not counting the initial position
+    pub fn num_movements(&self) -> usize {
+        self.movements.len() - 1
+    }
+
+    /// The length in the twist metric, which only counts the flips
+    pub fn num_flips(&self) -> usize {
+        self.movements[1..]
+            .iter()
+            .map(|movement| movement.change().num_flips())
+            .sum()
+    }
+
+    /// The length in the face turn metric, which counts the flips and the rotations of each layer
+    pub fn num_face_turns(&self) -> usize {
+        self.movements[1..]
+            .iter()
+            .map(|movement| movement.change().num_flips() + movement.change().num_turns())
+            .sum()
+    }
+}
+
+/// Solutions are serialized with their movements and their length in each metric
+impl Serialize for Solution {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Lengths {
+            compound: usize,
+            twist: usize,
+            face_turn: usize,
+        }
+
+        let mut solution = serializer.serialize_struct("Solution", 3)?;
+        solution.serialize_field("movements", &self.movements)?;
+        solution.serialize_field(
+            "lengths",
+            &Lengths {
+                compound: self.num_movements(),
+                twist: self.num_flips(),
+                face_turn: self.num_face_turns(),
+            },
+        )?;
+        solution.serialize_field("is_optimal", &self.is_optimal)?;
+        solution.end()
+    }
+}
 
-                next.movement.position().neighbours(&mut neighbours);
-                for &new_movement in neighbours.neighbours() {
-                    thread_explorer.enqueue(enqueued, new_movement);
+/// The resident memory of the process, read from `/proc` on Linux
+fn resident_memory() -> Option<usize> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} positions, {} distinct seen, {} in the queue, {} rejections",
+            format_big_int(self.iterations),
+            format_big_int(self.seen_positions),
+            format_big_int(self.queued),
+            format_big_int(self.rejections)
+        )?;
+        if let (Some(false_rejections), Some(false_positive_rate)) =
+            (self.false_rejections, self.false_positive_rate)
+        {
+            write!(
+                f,
+                " (about {} false, with a false positive rate of {:.2e})",
+                format_big_int(false_rejections),
+                false_positive_rate
+            )?;
+        }
+        write!(
+            f,
+            ", {}, {} steals",
+            self.pruned,
+            format_big_int(self.steals)
+        )
+    }
+}
+
+impl fmt::Display for SearchEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SearchEvent::SolutionFound {
+                num_movements,
+                elapsed_seconds,
+            } => write!(
+                f,
+                "Found a solution with {} movements after {:.1?}",
+                num_movements,
+                Duration::from_secs_f64(*elapsed_seconds)
+            ),
+            SearchEvent::WarmUpFinished { stats } => write!(f, "Warm up finished after {}", stats),
+            SearchEvent::ThreadSolved { thread, stats } => {
+                write!(f, "Thread {} solved after {}", thread, stats)
+            }
+            SearchEvent::Progress {
+                stats,
+                positions_per_second,
+                queue_sizes: _,
+                best_score,
+                memory_bytes,
+            } => {
+                write!(
+                    f,
+                    "Progress after {:.1?}: {} positions/s, best score {}",
+                    Duration::from_secs_f64(stats.elapsed_seconds),
+                    format_big_int(*positions_per_second as usize),
+                    best_score
+                )?;
+                if let Some(memory_bytes) = memory_bytes {
+                    write!(f, ", {}B of memory", format_big_int(*memory_bytes))?;
                 }
+                write!(f, ", {}", stats)
+            }
+            SearchEvent::Finished { stats } => write!(
+                f,
+                "Search finished in {:.1?} after {}",
+                Duration::from_secs_f64(stats.elapsed_seconds),
+                stats
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// A position 2 changes away from the solved one
+    fn scrambled() -> Position {
+        let mut neighbours = NeighboursStack::new();
+        Position::solved().neighbours(&mut neighbours);
+        neighbours.neighbours()[117].position()
+    }
+
+    #[test]
+    fn anytime_proves_optimality() {
+        let scrambled = scrambled();
+        let solution = solve_small(scrambled);
+
+        // A compound movement cannot be undone by a single one, since it ends with a flip
+        assert!(solution.is_optimal());
+        assert_eq!(solution.num_movements(), 2);
+        assert_eq!(solution.movements()[0].position(), scrambled);
+        assert_eq!(solution.movements()[2].position(), Position::solved());
+    }
+
+    #[test]
+    fn search_stats() {
+        let scrambled = scrambled();
+
+        let (solutions, stats) = test_solver()
+            .build()
+            .solve_many_with_stats(scrambled)
+            .unwrap();
+
+        assert_eq!(stats.num_solutions, 1);
+        assert_eq!(stats.queued, 0);
+        assert!(!stats.timed_out);
+        assert!(stats.iterations > 0);
+        assert!(stats.seen_positions > 1);
+        assert!(stats.pruned.total() > 0);
+
+        // A rotation at the end of the solution has no flips
+        let solution = &solutions[0];
+        assert!(solution.num_flips() <= 2 * solution.num_movements());
+        assert!(solution.num_face_turns() > solution.num_flips());
+
+        let event = serde_json::to_value(SearchEvent::Finished { stats }).unwrap();
+        assert_eq!(event["event"], "finished");
+        assert_eq!(event["stats"]["num_solutions"], 1);
+    }
+
+    #[test]
+    fn trace() {
+        let scrambled = scrambled();
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("trace");
+        let (_, stats) = test_solver()
+            .trace_file(&path)
+            .build()
+            .solve_many_with_stats(scrambled)
+            .unwrap();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let records = crate::trace::TraceReader::new(file)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(records.len(), stats.iterations);
+        assert_eq!(records[0].position, scrambled);
+        assert_eq!(records[0].parent, None);
+        assert!(records[1..].iter().all(|record| record.parent.is_some()));
+
+        // Every seen position but the initial one was enqueued, some of them more than once
+        let enqueued: usize = records.iter().map(|record| record.enqueued as usize).sum();
+        assert!(enqueued + 1 >= stats.seen_positions);
+    }
+
+    #[test]
+    fn progress() {
+        let counters = [
+            Counters {
+                iterations: 10,
+                rejections: 3,
+                steals: 0,
+                best_score: 40,
+                ..Counters::default()
+            },
+            Counters {
+                iterations: 5,
+                rejections: 1,
+                steals: 2,
+                best_score: 55,
+                ..Counters::default()
+            },
+        ];
+        let total = Counters::total(&counters);
+        assert_eq!(total.iterations, 15);
+        assert_eq!(total.rejections, 4);
+        assert_eq!(total.steals, 2);
+        assert_eq!(total.best_score, 55);
+
+        let event = SearchEvent::Progress {
+            stats: SearchStats::default(),
+            positions_per_second: 1e6,
+            queue_sizes: vec![3, 4],
+            best_score: total.best_score,
+            memory_bytes: Some(1 << 30),
+        };
+        assert!(event.to_string().starts_with("Progress after"));
+        let event = serde_json::to_value(event).unwrap();
+        assert_eq!(event["event"], "progress");
+        assert_eq!(event["queue_sizes"], serde_json::json!([3, 4]));
+    }
+
+    #[test]
+    fn threads_wait_for_active_ones() {
+        let mut main = MainExplorer::new(
+            scrambled(),
+            SearchMode::FirstSolution,
+            SeenStorage::Memory.create().unwrap(),
+            SymmetryGroup::Trivial,
+            Report::Silent,
+            Duration::from_secs(10),
+            None,
+        )
+        .unwrap();
+        let mut explorers = main.explode(2);
+        let mut idle = explorers.pop().unwrap();
+        let mut busy = explorers.pop().unwrap();
+        let node = busy.pop_queue().unwrap();
+
+        // The idle thread finds all the queues empty, but waits for the busy one to enqueue
+        crossbeam_utils::thread::scope(|scope| {
+            let waiting = scope.spawn(move |_| idle.pop_queue());
+            thread::sleep(Duration::from_millis(50));
+            busy.push_queue(node);
+            assert_eq!(waiting.join().unwrap(), Some(node));
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn idle_threads_exit_with_the_last_active_one() {
+        let mut main = MainExplorer::new(
+            scrambled(),
+            SearchMode::FirstSolution,
+            SeenStorage::Memory.create().unwrap(),
+            SymmetryGroup::Trivial,
+            Report::Silent,
+            Duration::from_secs(10),
+            None,
+        )
+        .unwrap();
+        let mut explorers = main.explode(2);
+        let mut idle = explorers.pop().unwrap();
+        let mut busy = explorers.pop().unwrap();
+        busy.pop_queue().unwrap();
+
+        // The busy thread finishes its node without enqueuing anything
+        crossbeam_utils::thread::scope(|scope| {
+            let waiting = scope.spawn(move |_| idle.pop_queue());
+            thread::sleep(Duration::from_millis(50));
+            busy.deactivate();
+            assert_eq!(waiting.join().unwrap(), None);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn packed_nodes() {
+        let change = Change::from_bytes(0x9123);
+        let node = VisitedNode::new(Some(MAX_NODES - 1), change);
+        assert_eq!(node.parent(), Some(MAX_NODES - 1));
+        assert_eq!(node.change(), change);
+        assert_eq!(VisitedNode::new(None, change).parent(), None);
+
+        let position = Position::solved();
+        let enqueued = Enqueued::new(position, position.score(), 17, MAX_NODES - 1);
+        assert_eq!(enqueued.score(), position.score());
+        assert_eq!(enqueued.depth(), 17);
+        assert_eq!(enqueued.index(), MAX_NODES - 1);
+
+        // A shallower node comes first, even with a smaller index
+        assert!(Enqueued::new(position, position.score(), 16, 0) > enqueued);
+        assert!(Enqueued::new(position, position.score(), 17, 0) < enqueued);
+    }
+
+    #[test]
+    fn stop_at_max_seen_positions() {
+        let scrambled = crate::scramble::scramble(7, 20);
+        let (solutions, stats) = test_solver()
+            .max_seen_positions(1)
+            .build()
+            .solve_many_with_stats(scrambled[scrambled.len() - 1].position())
+            .unwrap();
+
+        assert!(stats.seen_limit_reached);
+        assert!(!stats.timed_out);
+        // The neighbours of the initial position are enough to reach the limit
+        assert!(stats.iterations <= 2);
+        assert!(stats.seen_positions < 2_000);
+        assert!(solutions.iter().all(|solution| !solution.is_optimal()));
+    }
+
+    #[test]
+    fn silent_by_default() {
+        // The library doesn't write on the standard output unless asked to
+        assert_eq!(Solver::builder().build().report, Report::Silent);
+    }
+
+    #[test]
+    fn seen_positions_on_disk() {
+        let scrambled = scrambled();
+
+        let directory = tempfile::tempdir().unwrap();
+        let solution = test_solver()
+            .seen_storage(SeenStorage::Disk {
+                directory: directory.path().to_path_buf(),
+                // About 2 MB for the shards, and 1 MB for the entries
+                memory_bytes: 3 << 20,
+            })
+            .build()
+            .solve(scrambled)
+            .unwrap()
+            .unwrap();
+
+        assert!(solution.is_optimal());
+        assert_eq!(solution.num_movements(), 2);
+    }
+
+    #[test]
+    fn disk_errors_fail_the_search() {
+        // The first run can't be written where there is a directory
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::create_dir(directory.path().join("seen-0.bin")).unwrap();
+
+        let error = test_solver()
+            .seen_storage(SeenStorage::Disk {
+                directory: directory.path().to_path_buf(),
+                memory_bytes: 0,
+            })
+            .build()
+            .solve(scrambled())
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("failed to access the seen positions"));
+    }
+
+    #[test]
+    fn bloom_filter_is_not_optimal() {
+        let scrambled = scrambled();
+
+        let solution = test_solver()
+            .mode(SearchMode::Anytime {
+                time_limit: Some(Duration::from_secs(1)),
+            })
+            .seen_storage(SeenStorage::Bloom {
+                memory_bytes: 1 << 20,
+                num_hashes: 4,
+            })
+            .build()
+            .solve(scrambled)
+            .unwrap()
+            .unwrap();
+
+        // The solution may be optimal, but false positives can't rule out a shorter one
+        assert!(!solution.is_optimal());
+        assert_eq!(solution.movements()[0].position(), scrambled);
+    }
+
+    #[test]
+    fn multiple_solutions() {
+        let scrambled = scrambled();
+
+        let solutions = test_solver()
+            .mode(SearchMode::Multiple {
+                count: 10,
+                max_movements: Some(2),
+                time_limit: None,
+            })
+            .build()
+            .solve_many(scrambled)
+            .unwrap();
+
+        assert!(solutions.len() > 1);
+        assert!(solutions[0].is_optimal());
+        for (i, solution) in solutions.iter().enumerate() {
+            assert_eq!(solution.num_movements(), 2);
+            assert_eq!(solution.movements()[2].position(), Position::solved());
+
+            let changes = solution
+                .movements()
+                .iter()
+                .map(|m| m.change())
+                .collect_vec();
+            for other in &solutions[i + 1..] {
+                let other_changes = other.movements().iter().map(|m| m.change()).collect_vec();
+                assert_ne!(changes, other_changes);
             }
+        }
+    }
+
+    #[test]
+    fn final_rotation() {
+        let mut neighbours = NeighboursStack::new();
+        Position::solved().neighbours(&mut neighbours);
+        let rotated = neighbours
+            .neighbours()
+            .iter()
+            .find(|m| m.change().is_rotation() && m.position() != Position::solved())
+            .unwrap()
+            .position();
+
+        let solution = solve_small(rotated);
+
+        assert_eq!(solution.num_movements(), 1);
+        assert!(solution.movements()[1].change().is_rotation());
+        assert_eq!(solution.movements()[1].position(), Position::solved());
+    }
+
+    #[test]
+    fn symmetries_keep_optimality() {
+        let scrambled = scrambled();
+
+        let lengths = [SymmetryGroup::Trivial, SymmetryGroup::Full].map(|symmetries| {
+            let solution = test_solver()
+                .symmetries(symmetries)
+                .build()
+                .solve(scrambled)
+                .unwrap()
+                .unwrap();
+            assert!(solution.is_optimal());
+            solution.num_movements()
         });
 
-    explorer.solution()
+        assert_eq!(lengths[0], lengths[1]);
+    }
 }
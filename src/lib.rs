@@ -0,0 +1,48 @@
+pub mod batch;
+pub mod bloom_set;
+#[cfg(unix)]
+pub mod disk_set;
+pub mod enumeration;
+pub mod explain;
+pub mod find_solution;
+pub mod logger;
+mod move_tables;
+pub mod piece;
+pub mod position;
+pub mod ranking;
+mod rotatable_layer;
+mod scorable_layer;
+pub mod scramble;
+pub mod seen_set;
+pub mod shape;
+pub mod sharded_set;
+pub mod simplify;
+pub mod svg;
+pub mod symmetry;
+pub mod terminal;
+pub mod trace;
+pub mod viewer;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+use crate::piece::Piece;
+use crate::position::Position;
+use std::time::Duration;
+
+pub fn format_big_int(n: usize) -> String {
+    if n < 1_000 {
+        format!("{}", n)
+    } else if n < 1_000_000 {
+        format!("{:.1}k", n as f64 / 1e3)
+    } else if n < 1_000_000_000 {
+        format!("{:.1}M", n as f64 / 1e6)
+    } else {
+        format!("{:.1}G", n as f64 / 1e9)
+    }
+}
+
+/// A duration of a finite and non-negative number of seconds, as given on a command line or in a
+/// request
+pub fn duration(seconds: f64) -> Option<Duration> {
+    (seconds >= 0.0 && seconds.is_finite()).then(|| Duration::from_secs_f64(seconds))
+}
@@ -0,0 +1,140 @@
+//! A logger for the `log` facade, used by the binaries: a human-readable line on the standard
+//! error for each record, and optionally a JSON object per line in a file, to monitor long runs.
+//!
+//! The JSON objects have the time, the level, the target and the message of the record, and its
+//! key-values, like the [`crate::find_solution::SearchEvent`] of the solver under `event`.
+
+use anyhow::{Context, Result};
+use log::kv::{Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use parking_lot::Mutex;
+use serde_json::{json, Map};
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub struct Logger {
+    level: LevelFilter,
+    start: Instant,
+    /// Flushed after each record, so that the file can be followed during the run
+    json_file: Option<Mutex<LineWriter<File>>>,
+}
+
+/// Collects the key-values of a record as JSON
+struct JsonFields(Map<String, serde_json::Value>);
+
+impl Logger {
+    /// Log the records up to `level`, only on the standard error
+    pub fn new(level: LevelFilter) -> Self {
+        Logger {
+            level,
+            start: Instant::now(),
+            json_file: None,
+        }
+    }
+
+    /// Also write the records to a file, which is truncated first
+    pub fn json_file(mut self, path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create the log file {}", path.display()))?;
+        self.json_file = Some(Mutex::new(LineWriter::new(file)));
+        Ok(self)
+    }
+
+    /// Install this logger for the whole process. This fails if a logger was already installed.
+    pub fn init(self) -> Result<()> {
+        log::set_max_level(self.level);
+        log::set_boxed_logger(Box::new(self)).context("a logger was already installed")
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        eprintln!(
+            "[{:>9.3}s {:<5}] {}",
+            self.start.elapsed().as_secs_f64(),
+            record.level(),
+            record.args()
+        );
+
+        if let Some(json_file) = &self.json_file {
+            // Logging must not fail, and the records are not worth a panic
+            let _ = writeln!(json_file.lock(), "{}", json_record(record));
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(json_file) = &self.json_file {
+            let _ = json_file.lock().flush();
+        }
+    }
+}
+
+impl<'kvs> VisitSource<'kvs> for JsonFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = serde_json::to_value(value).map_err(log::kv::Error::boxed)?;
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+fn json_record(record: &Record) -> serde_json::Value {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |time| time.as_secs_f64());
+    let mut fields = JsonFields(Map::new());
+    let _ = record.key_values().visit(&mut fields);
+
+    let mut object = json!({
+        "time": time,
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    });
+    object
+        .as_object_mut()
+        .expect("the record is an object")
+        .extend(fields.0);
+    object
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_solution::SearchEvent;
+    use log::Level;
+
+    #[test]
+    fn json_records() {
+        let event = SearchEvent::SolutionFound {
+            num_movements: 12,
+            elapsed_seconds: 1.5,
+        };
+        let key_values = [("event", Value::from_serde(&event))];
+        let message = event.to_string();
+        let object = json_record(
+            &Record::builder()
+                .level(Level::Info)
+                .target("bachar_cube::find_solution")
+                .args(format_args!("{}", message))
+                .key_values(&key_values)
+                .build(),
+        );
+        assert_eq!(object["level"], "INFO");
+        assert_eq!(object["target"], "bachar_cube::find_solution");
+        assert_eq!(object["message"], message);
+        assert_eq!(object["event"]["event"], "solution_found");
+        assert_eq!(object["event"]["num_movements"], 12);
+        assert!(object["time"].as_f64().unwrap() > 0.0);
+    }
+}
@@ -1,19 +1,37 @@
-mod find_solution;
-mod piece;
-mod position;
-mod prefix_set;
-mod rotatable_layer;
-mod scorable_layer;
-
-use crate::piece::Piece;
-use crate::position::Position;
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use bachar_cube::batch::{read_positions, solve_batch, BatchSummary};
+use bachar_cube::duration;
+use bachar_cube::explain::explain;
+use bachar_cube::find_solution::{Report, SearchMode, Solver};
+use bachar_cube::logger::Logger;
+use bachar_cube::piece::Piece;
+use bachar_cube::position::Position;
+use bachar_cube::seen_set::SeenStorage;
+use bachar_cube::simplify::simplify;
+use bachar_cube::symmetry::SymmetryGroup;
+use bachar_cube::terminal::TerminalRenderer;
 use itertools::Itertools;
+use log::LevelFilter;
 use rayon::ThreadPoolBuilder;
-use std::time::Instant;
+use serde_json::json;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 const NUM_THREADS: usize = 16;
 
+/// The format of the standard output
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Output {
+    Text,
+    /// A single JSON document with the solution and the counters of the search, once it's over
+    Json,
+    /// A JSON object per line for each event of the search, then for the result
+    JsonLines,
+}
+
 fn main() -> Result<()> {
     let initial_position = Position::from_pieces([
         Piece::YellowOrange,
@@ -34,32 +52,277 @@ fn main() -> Result<()> {
         Piece::YellowBlue,
     ]);
 
-    println!("{}", Position::solved());
-    println!("{}", initial_position);
+    // An optional time limit in seconds switches to the anytime search, and an optional directory
+    // keeps the seen positions on the disk, or a Bloom filter replaces them. The symmetric positions
+    // are only explored once, unless disabled. The output is human-readable text unless
+    // `--output json` or `--output json-lines` is given. `--batch` solves the positions of a file
+    // instead, see `bachar_cube::batch`. The progress of the search is logged on the standard
+    // error, and also in a file of JSON lines with `--log-file`. `--trace` records the expansions
+    // of the search, to be summarized by the `trace` binary. `--diagrams` draws the position after
+    // each movement of the solution in the text output, in colours unless `NO_COLOR` is set.
+    // `--explain` walks through each twist of the solution, in the text output or under `steps`,
+    // and `--simplify` merges the turns of consecutive changes, in the text output or under
+    // `simplified`
+    let mut batch_file = None;
+    let mut log_level = LevelFilter::Info;
+    let mut log_file = None;
+    let mut progress_interval = Duration::from_secs(10);
+    let mut trace_file = None;
+    let mut diagrams = false;
+    let mut explain_solution = false;
+    let mut simplify_solution = false;
+    let mut output = Output::Text;
+    let mut mode = SearchMode::FirstSolution;
+    let mut symmetries = SymmetryGroup::Full;
+    let mut seen_directory = None;
+    let mut seen_memory_bytes = None;
+    let mut bloom_memory_bytes = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seen-directory" => {
+                seen_directory = Some(PathBuf::from(
+                    args.next().context("missing value for --seen-directory")?,
+                ));
+            }
+            "--seen-memory-gib" => {
+                seen_memory_bytes = Some(
+                    args.next()
+                        .context("missing value for --seen-memory-gib")?
+                        .parse()
+                        .ok()
+                        .and_then(memory_bytes)
+                        .context("--seen-memory-gib must be a positive number of GiB")?,
+                );
+            }
+            "--bloom-memory-gib" => {
+                bloom_memory_bytes = Some(
+                    args.next()
+                        .context("missing value for --bloom-memory-gib")?
+                        .parse()
+                        .ok()
+                        .and_then(memory_bytes)
+                        .context("--bloom-memory-gib must be a positive number of GiB")?,
+                );
+            }
+            "--batch" => {
+                batch_file = Some(PathBuf::from(
+                    args.next().context("missing value for --batch")?,
+                ));
+            }
+            "--log-level" => {
+                log_level = args
+                    .next()
+                    .context("missing value for --log-level")?
+                    .parse()
+                    .context("invalid log level")?;
+            }
+            "--log-file" => {
+                log_file = Some(PathBuf::from(
+                    args.next().context("missing value for --log-file")?,
+                ));
+            }
+            "--progress-interval" => {
+                progress_interval = args
+                    .next()
+                    .context("missing value for --progress-interval")?
+                    .parse()
+                    .ok()
+                    .and_then(duration)
+                    .context("invalid progress interval")?;
+            }
+            "--trace" => {
+                trace_file = Some(PathBuf::from(
+                    args.next().context("missing value for --trace")?,
+                ));
+            }
+            "--diagrams" => diagrams = true,
+            "--explain" => explain_solution = true,
+            "--simplify" => simplify_solution = true,
+            "--no-symmetries" => symmetries = SymmetryGroup::Trivial,
+            "--output" => {
+                output = match args.next().as_deref() {
+                    Some("text") => Output::Text,
+                    Some("json") => Output::Json,
+                    Some("json-lines") => Output::JsonLines,
+                    _ => bail!("--output must be text, json or json-lines"),
+                };
+            }
+            seconds if seconds.parse::<f64>().is_ok() => {
+                mode = SearchMode::Anytime {
+                    time_limit: Some(duration(seconds.parse()?).context("invalid time limit")?),
+                };
+            }
+            _ => bail!("unknown argument {}", arg),
+        }
+    }
+    ensure!(
+        seen_memory_bytes.is_none() || seen_directory.is_some(),
+        "--seen-memory-gib can only be used with --seen-directory"
+    );
+    let seen_storage = match (seen_directory, bloom_memory_bytes) {
+        (Some(_), Some(_)) => bail!("--seen-directory cannot be used with --bloom-memory-gib"),
+        (None, Some(memory_bytes)) => SeenStorage::Bloom {
+            memory_bytes,
+            num_hashes: 6,
+        },
+        (Some(directory), None) => SeenStorage::Disk {
+            directory,
+            memory_bytes: seen_memory_bytes.unwrap_or(4 << 30),
+        },
+        (None, None) => SeenStorage::Memory,
+    };
+
+    let mut logger = Logger::new(log_level);
+    if let Some(log_file) = log_file {
+        logger = logger.json_file(&log_file)?;
+    }
+    logger.init()?;
 
     ThreadPoolBuilder::new()
         .num_threads(NUM_THREADS)
         .build_global()?;
 
+    if let Some(batch_file) = batch_file {
+        ensure!(
+            trace_file.is_none(),
+            "the searches of a batch cannot share --trace"
+        );
+        ensure!(
+            !matches!(seen_storage, SeenStorage::Disk { .. }),
+            "the searches of a batch cannot share --seen-directory"
+        );
+        let file = File::open(&batch_file)
+            .with_context(|| format!("failed to open {}", batch_file.display()))?;
+        let positions = read_positions(BufReader::new(file))?;
+
+        // The positions share the threads, and each search gets a part of them so that they are
+        // all busy even with few positions
+        let solver = Solver::builder()
+            .warm_up(100_000)
+            .num_threads((NUM_THREADS / positions.len().max(1)).max(1))
+            .mode(mode)
+            .seen_storage(seen_storage)
+            .symmetries(symmetries)
+            .progress_interval(progress_interval)
+            .report(Report::Silent)
+            .build();
+        let results = solve_batch(&positions, &solver)?;
+        let summary = BatchSummary::new(&results);
+
+        match output {
+            Output::Text => {
+                for result in &results {
+                    println!("{}", result);
+                }
+                println!("{}", summary);
+            }
+            Output::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&json!({
+                    "results": results,
+                    "summary": summary,
+                }))?
+            ),
+            Output::JsonLines => {
+                for result in &results {
+                    let mut line = serde_json::to_value(result)?;
+                    line["event"] = json!("result");
+                    println!("{}", line);
+                }
+                println!("{}", json!({ "event": "summary", "summary": summary }));
+            }
+        }
+        return Ok(());
+    }
+
+    let renderer = TerminalRenderer::new().colours(env::var_os("NO_COLOR").is_none());
+    if output == Output::Text {
+        println!("{}", Position::solved());
+        println!("{}", initial_position);
+        if diagrams {
+            print!("{}", renderer.position(initial_position));
+        }
+    }
+
     let start = Instant::now();
-    let solution = find_solution::find_solution(initial_position, 100_000, NUM_THREADS)
+    let mut builder = Solver::builder()
+        .warm_up(100_000)
+        .num_threads(NUM_THREADS)
+        .mode(mode)
+        .seen_storage(seen_storage)
+        .symmetries(symmetries)
+        .progress_interval(progress_interval);
+    if let Some(trace_file) = trace_file {
+        builder = builder.trace_file(trace_file);
+    }
+    let (solutions, stats) = builder
+        .report(match output {
+            // The events are already logged
+            Output::Text => Report::Silent,
+            Output::Json => Report::Silent,
+            Output::JsonLines => Report::JsonLines,
+        })
+        .build()
+        .solve_many_with_stats(initial_position)?;
+    let solution = solutions
+        .into_iter()
+        .next()
         .context("expected a solution to be found")?;
-    println!("find_solution in {:?}", start.elapsed());
 
-    println!("{}", solution.iter().map(|m| m.change()).format(", "));
-    println!("Solution has {} steps", 6 * solution.len());
+    if output == Output::Text {
+        println!("find_solution in {:?}", start.elapsed());
+
+        println!(
+            "{}",
+            solution.movements().iter().map(|m| m.change()).format(", ")
+        );
+        println!("Solution has {} steps", 6 * solution.movements().len());
+        if solution.is_optimal() {
+            println!("The solution is optimal");
+        }
+        if simplify_solution {
+            println!("\nSimplified: {}", simplify(solution.movements())?);
+        }
+        if explain_solution {
+            println!();
+            for step in explain(&solution)? {
+                println!("{}", step);
+            }
+        }
+        if diagrams {
+            print!("\n{}", renderer.solution(&solution));
+        }
+        return Ok(());
+    }
+
+    let mut result = json!({
+        "position": initial_position,
+        "solution": solution,
+        "stats": stats,
+    });
+    if simplify_solution {
+        result["simplified"] = serde_json::to_value(simplify(solution.movements())?)?;
+    }
+    if explain_solution {
+        result["steps"] = serde_json::to_value(explain(&solution)?)?;
+    }
+    if output == Output::JsonLines {
+        result["event"] = json!("result");
+        println!("{}", serde_json::to_string(&result)?);
+    } else {
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    }
 
     Ok(())
 }
 
-fn format_big_int(n: usize) -> String {
-    if n < 1_000 {
-        format!("{}", n)
-    } else if n < 1_000_000 {
-        format!("{:.1}k", n as f64 / 1e3)
-    } else if n < 1_000_000_000 {
-        format!("{:.1}M", n as f64 / 1e6)
+/// The bytes of a positive amount of memory in GiB
+fn memory_bytes(gib: f64) -> Option<usize> {
+    let bytes = gib * (1u64 << 30) as f64;
+    if bytes >= 1.0 && bytes.is_finite() {
+        Some(bytes as usize)
     } else {
-        format!("{:.1}G", n as f64 / 1e9)
+        None
     }
 }
@@ -0,0 +1,145 @@
+//! Tables that only depend on which pieces are big, so that splitting a position and rotating a
+//! layer are lookups instead of scans of the pieces.
+
+use crate::position::BITS_PER_PIECE;
+use lazy_static::lazy_static;
+
+/// The valid rotations of a layer, given which of its pieces are big
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LayerRotations {
+    /// Bit `n` is set if the layer can be sliced after rotating it by `n` pieces
+    pub valid: u16,
+    /// The number of pieces in the second half-layer after rotating by `n` pieces
+    pub second_pieces: [u8; MAX_LAYER_PIECES],
+}
+
+/// A layer has at most 8 small pieces and 2 big ones, but the tables are also correct for layers
+/// that the puzzle can't have
+const MAX_LAYER_PIECES: usize = 12;
+
+lazy_static! {
+    /// For each pattern of big pieces in a position, the number of pieces in each half-layer, from
+    /// the top's first to the bottom's second. All zeros when the position cannot be sliced.
+    static ref SPLITS: Vec<[u8; 4]> = (0..1 << 16).map(|mask| split_mask(mask, 16)).collect();
+
+    /// Indexed by [`layer_key()`]
+    static ref ROTATIONS: Vec<LayerRotations> = (0..1 << (MAX_LAYER_PIECES + 1))
+        .map(|key: u32| {
+            let num_pieces = (u32::BITS - key.leading_zeros()).saturating_sub(1) as usize;
+            layer_rotations_of_mask(key & ((1 << num_pieces) - 1), num_pieces)
+        })
+        .collect();
+}
+
+/// The number of pieces in each half-layer of the position, from the top's first to the bottom's
+/// second, or `None` if it cannot be sliced
+pub fn split(bits: u64) -> Option<[u8; 4]> {
+    let split = SPLITS[big_pieces_mask(bits) as usize];
+    if split[0] == 0 {
+        None
+    } else {
+        Some(split)
+    }
+}
+
+/// The valid rotations of a layer of `num_pieces`, represented like [`crate::Position`]
+pub fn layer_rotations(bits: u64, num_pieces: u32) -> &'static LayerRotations {
+    &ROTATIONS[layer_key(big_pieces_mask(bits), num_pieces)]
+}
+
+/// A leading bit tells the number of pieces, so that each layer has a distinct key
+fn layer_key(mask: u64, num_pieces: u32) -> usize {
+    ((1 << num_pieces) | mask) as usize
+}
+
+/// One bit per piece, set for big pieces, with the last piece in the least significant bit
+pub fn big_pieces_mask(bits: u64) -> u64 {
+    // Gather the size bit of each group of 4 bits
+    let mut mask = (bits >> 1) & 0x1111_1111_1111_1111;
+    mask = (mask | (mask >> 3)) & 0x0303_0303_0303_0303;
+    mask = (mask | (mask >> 6)) & 0x000F_000F_000F_000F;
+    mask = (mask | (mask >> 12)) & 0x0000_00FF_0000_00FF;
+    (mask | (mask >> 24)) & 0xFFFF
+}
+
+/// Split the pieces into half-layers of 6 units, starting from the last piece
+fn split_mask(mask: u32, num_pieces: usize) -> [u8; 4] {
+    let mut split = [0; 4];
+    let mut half = 3;
+    let mut half_size = 0;
+    for n in 0..num_pieces {
+        split[half] += 1;
+        half_size += 1 + ((mask >> n) & 1);
+
+        if half_size > 6 {
+            return [0; 4];
+        } else if half_size == 6 {
+            half_size = 0;
+            if half == 0 {
+                // Every piece must be used
+                return if n == num_pieces - 1 { split } else { [0; 4] };
+            }
+            half -= 1;
+        }
+    }
+
+    [0; 4]
+}
+
+/// The number of the last pieces that form a half-layer of 6 units, if any
+fn second_half_pieces(mask: u32, num_pieces: usize) -> Option<u8> {
+    let mut size = 0;
+    for n in 0..num_pieces {
+        size += 1 + ((mask >> n) & 1);
+        if size == 6 {
+            return Some(n as u8 + 1);
+        } else if size > 6 {
+            return None;
+        }
+    }
+    None
+}
+
+fn layer_rotations_of_mask(mask: u32, num_pieces: usize) -> LayerRotations {
+    let mut rotations = LayerRotations::default();
+    for n in 0..num_pieces {
+        // Moving the last `n` pieces to the front
+        let rotated = (mask >> n) | ((mask << (num_pieces - n)) & ((1 << num_pieces) - 1));
+        if let Some(second_pieces) = second_half_pieces(rotated, num_pieces) {
+            rotations.valid |= 1 << n;
+            rotations.second_pieces[n] = second_pieces;
+        }
+    }
+    rotations
+}
+
+/// Rotate a layer of `num_pieces` by moving the last `n` pieces to the front
+pub fn rotate(bits: u64, num_pieces: u32, n: u32) -> u64 {
+    let width = BITS_PER_PIECE * num_pieces;
+    let shift = BITS_PER_PIECE * n;
+    (bits >> shift) | ((bits << (width - shift)) & ((1 << width) - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Position;
+
+    #[test]
+    fn split_solved() {
+        assert_eq!(split(Position::solved().as_bytes()), Some([4, 4, 4, 4]));
+        assert_eq!(
+            big_pieces_mask(Position::solved().as_bytes()),
+            0b1010_1010_0101_0101
+        );
+    }
+
+    #[test]
+    fn unsliceable() {
+        assert_eq!(split_mask(0b0101_0101_1010_1010, 16), [4, 4, 4, 4]);
+        // The big piece at index 4 would cross the end of the first half-layer
+        assert_eq!(split_mask(0b1011_0101_0101_0100, 16), [0; 4]);
+        assert_eq!(second_half_pieces(0b0100, 4), None);
+        assert_eq!(second_half_pieces(0b0110, 4), Some(4));
+    }
+}
@@ -1,4 +1,6 @@
+use anyhow::{anyhow, Error, Result};
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum Piece {
@@ -126,3 +128,15 @@ impl fmt::Display for Piece {
         f.write_str(s)
     }
 }
+
+impl FromStr for Piece {
+    type Err = Error;
+
+    /// Parse the name written by [`Piece`]'s `Display`, like `WRB`
+    fn from_str(s: &str) -> Result<Self> {
+        (0..16)
+            .map(Piece::from_bits)
+            .find(|piece| piece.to_string() == s)
+            .ok_or_else(|| anyhow!("invalid piece: {}", s))
+    }
+}
@@ -1,16 +1,23 @@
+use crate::format_big_int;
+use crate::move_tables;
 use crate::piece::Piece;
 use crate::rotatable_layer::RotatableLayer;
-use crate::scorable_layer::ScorableLayer;
+use crate::scorable_layer::{self, ScorableLayer};
+use anyhow::{ensure, Context, Error, Result};
 use itertools::Itertools;
+use lazy_static::lazy_static;
+use serde::{Serialize, Serializer};
 use std::fmt;
 use std::fmt::Write;
+use std::ops::{AddAssign, ControlFlow};
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct Position {
     pieces: u64,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct Change {
     top_before: u8,
     bottom_before: u8,
@@ -18,7 +25,7 @@ pub struct Change {
     bottom_after: u8,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub struct Movement {
     change: Change,
     position: Position,
@@ -29,10 +36,66 @@ pub struct Movement {
 #[derive(Debug, Clone)]
 pub struct NeighboursStack {
     neighbours: Vec<Movement>,
-    top_before_rotations: Vec<(RotatableLayer, u8)>,
-    bottom_before_rotations: Vec<(RotatableLayer, u8)>,
-    top_after_rotations: Vec<(RotatableLayer, u8)>,
-    bottom_after_rotations: Vec<(RotatableLayer, u8)>,
+    twists: Vec<Position>,
+    rotations: RotationsStack,
+    pruned: PruningStats,
+}
+
+/// How many branches were pruned by [`Position::visit_neighbours()`], for each rule
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize)]
+pub struct PruningStats {
+    /// Changes that only rotate the layers
+    pub rotations: u64,
+    /// Rotations before the first flip that were not done, with all the changes that start with
+    /// them, because the flip would cancel the previous one
+    pub cancelled_flips: u64,
+}
+
+/// The rules applied by [`Position::generate_neighbours()`]
+#[derive(Debug, Clone, Copy, Default)]
+struct Pruning {
+    rotations: bool,
+    cancelled_flips: bool,
+}
+
+/// The rotations of each layer, before and after the first flip
+#[derive(Debug, Clone)]
+struct RotationsStack {
+    top_before: Vec<(RotatableLayer, u8)>,
+    bottom_before: Vec<(RotatableLayer, u8)>,
+    top_after: Vec<(RotatableLayer, u8)>,
+    bottom_after: Vec<(RotatableLayer, u8)>,
+}
+
+lazy_static! {
+    /// The positions that only differ from the solved one by the rotation of the layers, sorted,
+    /// with the change that solves them
+    static ref SOLVING_ROTATIONS: Vec<(Position, Change)> = {
+        let mut stack = NeighboursStack::new();
+        Position::solved().neighbours(&mut stack);
+        let rotations = stack
+            .neighbours()
+            .iter()
+            .filter(|movement| movement.change.is_rotation())
+            .map(|movement| movement.position)
+            .collect_vec();
+
+        rotations
+            .into_iter()
+            .map(|rotated| {
+                rotated.neighbours(&mut stack);
+                let solving = stack
+                    .neighbours()
+                    .iter()
+                    .find(|movement| {
+                        movement.change.is_rotation() && movement.position == Position::solved()
+                    })
+                    .expect("rotations can be undone");
+                (rotated, solving.change)
+            })
+            .sorted_by_key(|&(position, _)| position)
+            .collect()
+    };
 }
 
 pub const BITS_PER_PIECE: u32 = 4;
@@ -89,24 +152,87 @@ impl Position {
     pub fn neighbours(&self, stack: &mut NeighboursStack) {
         stack.neighbours.clear();
 
+        let neighbours = &mut stack.neighbours;
+        let _ = self.generate_neighbours(
+            &mut stack.rotations,
+            Pruning::default(),
+            &mut stack.pruned,
+            |movement| {
+                neighbours.push(movement);
+                ControlFlow::Continue(())
+            },
+        );
+    }
+
+    /// Stream the neighbours to `visit` as they are generated, without storing them. Returning
+    /// [`ControlFlow::Break`] stops the generation, and is returned.
+    ///
+    /// Unlike [`Position::neighbours()`], this prunes the changes that can't lead to a shorter
+    /// solution, counting them in [`NeighboursStack::take_pruned()`]:
+    /// - the changes that only rotate the layers, since both flips cancel out. Every neighbour of
+    ///   such a rotated position is also a neighbour of this position, so the only use of a
+    ///   rotation is to align the layers at the end of a solution, see
+    ///   [`Position::solving_rotation()`]
+    /// - when `after_movement`, the changes that don't rotate the layers before flipping, since
+    ///   that flip cancels the last one of the previous movement. Both movements together are a
+    ///   single movement from the previous position.
+    ///
+    /// The rotations of the top and bottom layers commute, so they are already generated only
+    /// once, as the two amounts of a single [`Change`].
+    pub fn visit_neighbours<F>(
+        &self,
+        stack: &mut NeighboursStack,
+        after_movement: bool,
+        visit: F,
+    ) -> ControlFlow<()>
+    where
+        F: FnMut(Movement) -> ControlFlow<()>,
+    {
+        let pruning = Pruning {
+            rotations: true,
+            cancelled_flips: after_movement,
+        };
+        self.generate_neighbours(&mut stack.rotations, pruning, &mut stack.pruned, visit)
+    }
+
+    fn generate_neighbours<F>(
+        &self,
+        rotations: &mut RotationsStack,
+        pruning: Pruning,
+        pruned: &mut PruningStats,
+        mut visit: F,
+    ) -> ControlFlow<()>
+    where
+        F: FnMut(Movement) -> ControlFlow<()>,
+    {
         let (top, bottom) = RotatableLayer::split(self.pieces);
-        top.rotations(&mut stack.top_before_rotations);
-        bottom.rotations(&mut stack.bottom_before_rotations);
+        top.rotations(&mut rotations.top_before);
+        bottom.rotations(&mut rotations.bottom_before);
+
+        for &(rotated_top, top_before) in &rotations.top_before {
+            for &(rotated_bottom, bottom_before) in &rotations.bottom_before {
+                if pruning.cancelled_flips && top_before == 0 && bottom_before == 0 {
+                    pruned.cancelled_flips += 1;
+                    continue;
+                }
 
-        for &(rotated_top, top_before) in &stack.top_before_rotations {
-            for &(rotated_bottom, bottom_before) in &stack.bottom_before_rotations {
                 let (flipped_top, flipped_bottom) =
                     RotatableLayer::flip(rotated_top, rotated_bottom);
 
-                flipped_top.rotations(&mut stack.top_after_rotations);
-                flipped_bottom.rotations(&mut stack.bottom_after_rotations);
+                flipped_top.rotations(&mut rotations.top_after);
+                flipped_bottom.rotations(&mut rotations.bottom_after);
+
+                for &(rotated_top, top_after) in &rotations.top_after {
+                    for &(rotated_bottom, bottom_after) in &rotations.bottom_after {
+                        if pruning.rotations && top_after == 0 && bottom_after == 0 {
+                            pruned.rotations += 1;
+                            continue;
+                        }
 
-                for &(rotated_top, top_after) in &stack.top_after_rotations {
-                    for &(rotated_bottom, bottom_after) in &stack.bottom_after_rotations {
                         let (flipped_top, flipped_bottom) =
                             RotatableLayer::flip(rotated_top, rotated_bottom);
 
-                        stack.neighbours.push(Movement {
+                        visit(Movement {
                             change: Change {
                                 top_before,
                                 bottom_before,
@@ -116,11 +242,73 @@ impl Position {
                             position: Position {
                                 pieces: RotatableLayer::join(flipped_top, flipped_bottom),
                             },
-                        });
+                        })?;
                     }
                 }
             }
         }
+
+        ControlFlow::Continue(())
+    }
+
+    /// Generate all positions that can be reached by rotating each layer and then flipping once.
+    ///
+    /// Unlike [`Position::neighbours()`], which flips twice, this changes the orientation of the
+    /// middle layer.
+    pub fn twists(&self, stack: &mut NeighboursStack) {
+        stack.twists.clear();
+
+        let rotations = &mut stack.rotations;
+        let (top, bottom) = RotatableLayer::split(self.pieces);
+        top.rotations(&mut rotations.top_before);
+        bottom.rotations(&mut rotations.bottom_before);
+
+        for &(rotated_top, _) in &rotations.top_before {
+            for &(rotated_bottom, _) in &rotations.bottom_before {
+                let (flipped_top, flipped_bottom) =
+                    RotatableLayer::flip(rotated_top, rotated_bottom);
+
+                stack.twists.push(Position {
+                    pieces: RotatableLayer::join(flipped_top, flipped_bottom),
+                });
+            }
+        }
+    }
+
+    /// Perform the change, returning the position it leads to, or `None` if one of its rotations
+    /// is not possible. This is slower than [`Position::neighbours()`] for a single change, since
+    /// it's meant to replay the changes of a solution.
+    pub fn apply(self, change: Change) -> Option<Self> {
+        fn rotate(layer: RotatableLayer, amount: u8) -> Option<RotatableLayer> {
+            let mut rotations = Vec::new();
+            layer.rotations(&mut rotations);
+            rotations
+                .into_iter()
+                .find(|&(_, n)| n == amount)
+                .map(|(rotated, _)| rotated)
+        }
+
+        let (top, bottom) = RotatableLayer::split(self.pieces);
+        let (top, bottom) = RotatableLayer::flip(
+            rotate(top, change.top_before)?,
+            rotate(bottom, change.bottom_before)?,
+        );
+        let (top, bottom) = RotatableLayer::flip(
+            rotate(top, change.top_after)?,
+            rotate(bottom, change.bottom_after)?,
+        );
+
+        Some(Position {
+            pieces: RotatableLayer::join(top, bottom),
+        })
+    }
+
+    /// The change that only rotates the layers to reach the solved position, if any
+    pub fn solving_rotation(self) -> Option<Change> {
+        SOLVING_ROTATIONS
+            .binary_search_by_key(&self, |&(position, _)| position)
+            .ok()
+            .map(|index| SOLVING_ROTATIONS[index].1)
     }
 
     pub fn score(self) -> u8 {
@@ -128,9 +316,75 @@ impl Position {
         top.score() + bottom.score()
     }
 
+    /// Score every position like [`Position::score()`], writing the scores in the same order.
+    /// It's faster than scoring them one by one.
+    pub fn score_batch(positions: &[Position], scores: &mut Vec<u8>) {
+        scorable_layer::score_batch(positions, scores)
+    }
+
     pub fn as_bytes(self) -> u64 {
         self.pieces
     }
+
+    /// The inverse of [`Position::as_bytes()`]. It's assumed that the value represents a valid
+    /// position.
+    pub fn from_bytes(bytes: u64) -> Self {
+        Position { pieces: bytes }
+    }
+}
+
+impl Change {
+    /// Whether the change only rotates the layers, since the second flip undoes the first one
+    pub fn is_rotation(self) -> bool {
+        self.top_after == 0 && self.bottom_after == 0
+    }
+
+    /// The rotations of the top and bottom layers before the first flip, then before the second
+    /// one. Each rotation is the number of pieces moved from the end of the layer to its start.
+    pub fn rotations(self) -> [u8; 4] {
+        [
+            self.top_before,
+            self.bottom_before,
+            self.top_after,
+            self.bottom_after,
+        ]
+    }
+
+    /// The number of flips, which is the length of the change in the twist metric. A rotation has
+    /// none, since its flips cancel out.
+    pub fn num_flips(self) -> usize {
+        if self.is_rotation() {
+            0
+        } else {
+            2
+        }
+    }
+
+    /// The number of layers that are rotated, counting the top and bottom ones separately
+    pub fn num_turns(self) -> usize {
+        self.rotations()
+            .iter()
+            .filter(|&&rotation| rotation != 0)
+            .count()
+    }
+
+    /// Pack the rotations into 4 bits each. A layer has at most 10 pieces, so each rotation fits.
+    pub fn as_bytes(self) -> u16 {
+        (self.top_before as u16) << 12
+            | (self.bottom_before as u16) << 8
+            | (self.top_after as u16) << 4
+            | self.bottom_after as u16
+    }
+
+    /// The inverse of [`Change::as_bytes()`]
+    pub fn from_bytes(bytes: u16) -> Self {
+        Change {
+            top_before: (bytes >> 12) as u8,
+            bottom_before: (bytes >> 8 & 0xF) as u8,
+            top_after: (bytes >> 4 & 0xF) as u8,
+            bottom_after: (bytes & 0xF) as u8,
+        }
+    }
 }
 
 impl NeighboursStack {
@@ -139,19 +393,61 @@ impl NeighboursStack {
             // Worst-case scenario: each one of `top_before`, `bottom_before`, `top_after`,
             // `bottom_after` goes from 0 to 9 (inclusive).
             neighbours: Vec::with_capacity(10_000),
-            top_before_rotations: Vec::with_capacity(10),
-            bottom_before_rotations: Vec::with_capacity(10),
-            top_after_rotations: Vec::with_capacity(10),
-            bottom_after_rotations: Vec::with_capacity(10),
+            twists: Vec::with_capacity(100),
+            rotations: RotationsStack {
+                top_before: Vec::with_capacity(10),
+                bottom_before: Vec::with_capacity(10),
+                top_after: Vec::with_capacity(10),
+                bottom_after: Vec::with_capacity(10),
+            },
+            pruned: PruningStats::default(),
         }
     }
 
     pub fn neighbours(&self) -> &[Movement] {
         &self.neighbours
     }
+
+    pub fn twists(&self) -> &[Position] {
+        &self.twists
+    }
+
+    /// The branches pruned since the last call, see [`Position::visit_neighbours()`]
+    pub fn take_pruned(&mut self) -> PruningStats {
+        std::mem::take(&mut self.pruned)
+    }
+}
+
+impl PruningStats {
+    pub fn total(self) -> u64 {
+        self.rotations + self.cancelled_flips
+    }
+}
+
+impl AddAssign for PruningStats {
+    fn add_assign(&mut self, other: Self) {
+        self.rotations += other.rotations;
+        self.cancelled_flips += other.cancelled_flips;
+    }
+}
+
+impl fmt::Display for PruningStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} pruned ({} rotations, {} cancelled flips)",
+            format_big_int(self.total() as usize),
+            format_big_int(self.rotations as usize),
+            format_big_int(self.cancelled_flips as usize)
+        )
+    }
 }
 
 impl Movement {
+    pub fn new(change: Change, position: Position) -> Self {
+        Movement { change, position }
+    }
+
     pub fn initial_movement(position: Position) -> Self {
         Movement {
             change: Change {
@@ -210,6 +506,73 @@ impl fmt::Display for Change {
     }
 }
 
+/// Positions are serialized like their `Display`
+impl Serialize for Position {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Changes are serialized like their `Display`
+impl Serialize for Change {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl FromStr for Position {
+    type Err = Error;
+
+    /// Parse the pieces written by [`Position`]'s `Display`, like `WRB WB WBO WO | WOG ...`. The
+    /// `|` are optional, but the layers must be sliceable.
+    fn from_str(s: &str) -> Result<Self> {
+        let pieces = s
+            .split_whitespace()
+            .filter(|&name| name != "|")
+            .map(Piece::from_str)
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(
+            pieces.len() == 16 && pieces.iter().unique().count() == 16,
+            "expected each of the 16 pieces once"
+        );
+
+        let position = Position::from_pieces(pieces.try_into().expect("there are 16 pieces"));
+        ensure!(
+            move_tables::split(position.pieces).is_some(),
+            "the position cannot be sliced"
+        );
+        Ok(position)
+    }
+}
+
+impl FromStr for Change {
+    type Err = Error;
+
+    /// Parse the rotations written by [`Change`]'s `Display`, like `T1B0T3B2`
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || format!("invalid change: {}", s);
+        let rest = s.strip_prefix('T').with_context(invalid)?;
+        let (top_before, rest) = rest.split_once('B').with_context(invalid)?;
+        let (bottom_before, rest) = rest.split_once('T').with_context(invalid)?;
+        let (top_after, bottom_after) = rest.split_once('B').with_context(invalid)?;
+
+        let rotations = [top_before, bottom_before, top_after, bottom_after]
+            .iter()
+            .map(|rotation| match rotation.parse::<u8>() {
+                Ok(rotation) if rotation < 16 => Ok(rotation),
+                _ => Err(Error::msg(invalid())),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Change {
+            top_before: rotations[0],
+            bottom_before: rotations[1],
+            top_after: rotations[2],
+            bottom_after: rotations[3],
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -298,6 +661,138 @@ mod tests {
         );
     }
 
+    #[test]
+    fn visit_neighbours() {
+        let mut stack = NeighboursStack::new();
+        Position::solved().neighbours(&mut stack);
+        let position = stack.neighbours()[117].position();
+        position.neighbours(&mut stack);
+        let expected = stack
+            .neighbours()
+            .iter()
+            .filter(|movement| !movement.change.is_rotation())
+            .map(|movement| movement.position)
+            .collect_vec();
+
+        let mut visited = vec![];
+        let flow = position.visit_neighbours(&mut stack, false, |movement| {
+            visited.push(movement.position);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(flow, ControlFlow::Continue(()));
+        assert_eq!(visited, expected);
+        let pruned = stack.take_pruned();
+        assert_eq!(
+            pruned.rotations as usize,
+            stack.neighbours().len() - expected.len()
+        );
+        assert_eq!(pruned.cancelled_flips, 0);
+
+        let mut num_visited = 0;
+        let flow = position.visit_neighbours(&mut stack, false, |_| {
+            num_visited += 1;
+            if num_visited == 10 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(flow, ControlFlow::Break(()));
+        assert_eq!(num_visited, 10);
+    }
+
+    #[test]
+    fn cancelled_flips() {
+        let mut stack = NeighboursStack::new();
+        Position::solved().neighbours(&mut stack);
+        let from_solved: BTreeSet<_> = stack.neighbours().iter().map(|m| m.position).collect();
+        let position = stack.neighbours()[117].position();
+
+        // The pruned changes lead to positions that are a single movement away from the solved one
+        position.neighbours(&mut stack);
+        let cancelled = stack
+            .neighbours()
+            .iter()
+            .filter(|m| m.change.top_before == 0 && m.change.bottom_before == 0)
+            .map(|m| m.position)
+            .collect_vec();
+        assert!(cancelled.iter().all(|p| from_solved.contains(p)));
+
+        let mut visited = BTreeSet::new();
+        let _ = position.visit_neighbours(&mut stack, true, |movement| {
+            visited.insert(movement.position);
+            ControlFlow::Continue(())
+        });
+        assert!(cancelled.iter().all(|p| !visited.contains(p)));
+        assert_eq!(stack.take_pruned().cancelled_flips, 1);
+    }
+
+    #[test]
+    fn solving_rotation() {
+        let mut stack = NeighboursStack::new();
+        Position::solved().neighbours(&mut stack);
+
+        for movement in stack.neighbours() {
+            let position = movement.position();
+            match position.solving_rotation() {
+                Some(rotation) => {
+                    assert!(movement.change().is_rotation());
+                    assert_eq!(position.apply(rotation), Some(Position::solved()));
+                }
+                None => assert!(!movement.change().is_rotation()),
+            }
+        }
+    }
+
+    #[test]
+    fn apply() {
+        let mut neighbours = NeighboursStack::new();
+        Position::solved().neighbours(&mut neighbours);
+
+        for movement in neighbours.neighbours() {
+            let change = Change::from_bytes(movement.change().as_bytes());
+            assert_eq!(change, movement.change());
+            assert_eq!(Position::solved().apply(change), Some(movement.position()));
+        }
+    }
+
+    #[test]
+    fn metrics() {
+        let change = Change::from_bytes(0x3021);
+        assert_eq!(change.num_flips(), 2);
+        assert_eq!(change.num_turns(), 3);
+
+        let rotation = Change::from_bytes(0x3100);
+        assert_eq!(rotation.num_flips(), 0);
+        assert_eq!(rotation.num_turns(), 2);
+    }
+
+    #[test]
+    fn parse() {
+        let mut neighbours = NeighboursStack::new();
+        Position::solved().neighbours(&mut neighbours);
+
+        for movement in neighbours.neighbours() {
+            let position = movement.position();
+            assert_eq!(position.to_string().parse::<Position>().unwrap(), position);
+            let change = movement.change();
+            assert_eq!(change.to_string().parse::<Change>().unwrap(), change);
+        }
+
+        let solved = Position::solved().to_string();
+        assert_eq!(
+            solved.replace(" | ", " ").parse::<Position>().unwrap(),
+            Position::solved()
+        );
+        assert!(solved.replace("WRB", "WB").parse::<Position>().is_err());
+        assert!(solved
+            .replace("WO | WOG", "WOG WO")
+            .parse::<Position>()
+            .is_err());
+        assert!("T1B2T3".parse::<Change>().is_err());
+        assert!("T1B2T3B16".parse::<Change>().is_err());
+    }
+
     #[test]
     fn score() {
         assert_eq!(Position::solved().score(), 16);
@@ -0,0 +1,326 @@
+//! Bijections between the valid states and dense integer ranges, to index tables by state.
+//!
+//! A position is ranked from three independent parts:
+//! - its shape: which pieces are big, ranked from `0` to [`NUM_SHAPES`]
+//! - the permutation of the big pieces, ranked from `0` to [`NUM_PERMUTATIONS`]
+//! - the permutation of the small pieces, ranked from `0` to [`NUM_PERMUTATIONS`]
+//!
+//! The orientation of the middle layer can also be included, for a total of [`NUM_STATES`].
+
+use crate::position::{BITS_PER_PIECE, LAST_PIECE_MASK};
+use crate::Position;
+
+/// The number of ways to split the pieces into four half-layers, without considering which piece
+/// goes where
+pub const NUM_SHAPES: u64 = COMPLETIONS[4][8];
+
+/// The number of permutations of the 8 big pieces or of the 8 small pieces
+pub const NUM_PERMUTATIONS: u64 = 40_320;
+
+pub const NUM_POSITIONS: u64 = NUM_SHAPES * NUM_PERMUTATIONS * NUM_PERMUTATIONS;
+
+/// The number of positions times the two orientations of the middle layer
+pub const NUM_STATES: u64 = 2 * NUM_POSITIONS;
+
+/// Each possible half-layer, as a bit pattern where each bit is set for a big piece (the first
+/// piece in the most significant bit) and its number of pieces
+const HALF_LAYERS: [(u8, u32); 13] = [
+    (0b111, 3),
+    (0b0011, 4),
+    (0b0101, 4),
+    (0b0110, 4),
+    (0b1001, 4),
+    (0b1010, 4),
+    (0b1100, 4),
+    (0b00001, 5),
+    (0b00010, 5),
+    (0b00100, 5),
+    (0b01000, 5),
+    (0b10000, 5),
+    (0b000000, 6),
+];
+
+/// `COMPLETIONS[n][b]` is the number of sequences of `n` half-layers with `b` big pieces in total
+const COMPLETIONS: [[u64; 9]; 5] = completions();
+
+const fn completions() -> [[u64; 9]; 5] {
+    let mut completions = [[0; 9]; 5];
+    completions[0][0] = 1;
+
+    let mut num_halves = 1;
+    while num_halves < 5 {
+        let mut half = 0;
+        while half < HALF_LAYERS.len() {
+            let half_big = HALF_LAYERS[half].0.count_ones() as usize;
+            let mut num_big = half_big;
+            while num_big < 9 {
+                completions[num_halves][num_big] += completions[num_halves - 1][num_big - half_big];
+                num_big += 1;
+            }
+            half += 1;
+        }
+        num_halves += 1;
+    }
+
+    completions
+}
+
+impl Position {
+    /// A dense index from `0` to [`NUM_POSITIONS`]
+    pub fn rank(self) -> u64 {
+        (self.shape_rank() * NUM_PERMUTATIONS + self.big_permutation_rank()) * NUM_PERMUTATIONS
+            + self.small_permutation_rank()
+    }
+
+    /// The inverse of [`Position::rank()`]
+    pub fn unrank(rank: u64) -> Self {
+        let small_permutation = rank % NUM_PERMUTATIONS;
+        let rank = rank / NUM_PERMUTATIONS;
+        Position::from_ranks(
+            rank / NUM_PERMUTATIONS,
+            rank % NUM_PERMUTATIONS,
+            small_permutation,
+        )
+    }
+
+    /// A dense index from `0` to [`NUM_STATES`], which includes the orientation of the middle
+    /// layer
+    pub fn rank_with_middle(self, middle_flipped: bool) -> u64 {
+        2 * self.rank() + middle_flipped as u64
+    }
+
+    /// The inverse of [`Position::rank_with_middle()`]
+    pub fn unrank_with_middle(rank: u64) -> (Self, bool) {
+        (Position::unrank(rank / 2), rank % 2 == 1)
+    }
+
+    /// A dense index from `0` to [`NUM_SHAPES`] that only depends on which pieces are big
+    pub fn shape_rank(self) -> u64 {
+        let mut rank = 0;
+        let mut num_big = 8;
+        for (n, half) in self.half_layers().into_iter().enumerate() {
+            let remaining_halves = 3 - n;
+            for &(other_half, _) in &HALF_LAYERS[..half] {
+                let other_big = other_half.count_ones() as usize;
+                if other_big <= num_big {
+                    rank += COMPLETIONS[remaining_halves][num_big - other_big];
+                }
+            }
+            num_big -= HALF_LAYERS[half].0.count_ones() as usize;
+        }
+        rank
+    }
+
+    /// A dense index from `0` to [`NUM_PERMUTATIONS`] of the order of the big pieces
+    pub fn big_permutation_rank(self) -> u64 {
+        rank_permutation(self.piece_indexes(true))
+    }
+
+    /// A dense index from `0` to [`NUM_PERMUTATIONS`] of the order of the small pieces
+    pub fn small_permutation_rank(self) -> u64 {
+        rank_permutation(self.piece_indexes(false))
+    }
+
+    /// Build the position from each of its independent ranks
+    pub fn from_ranks(
+        shape_rank: u64,
+        big_permutation_rank: u64,
+        small_permutation_rank: u64,
+    ) -> Self {
+        let big_pieces = unrank_permutation(big_permutation_rank);
+        let small_pieces = unrank_permutation(small_permutation_rank);
+        let mut num_big = 0;
+        let mut num_small = 0;
+
+        let mut bits = 0;
+        for half in unrank_shape(shape_rank) {
+            let (half_bits, half_pieces) = HALF_LAYERS[half];
+            for n in (0..half_pieces).rev() {
+                let is_big = (half_bits >> n) & 1 == 1;
+                let index = if is_big {
+                    num_big += 1;
+                    big_pieces[num_big - 1]
+                } else {
+                    num_small += 1;
+                    small_pieces[num_small - 1]
+                };
+
+                bits = (bits << BITS_PER_PIECE) | piece_from_index(index, is_big);
+            }
+        }
+
+        Position::from_bytes(bits)
+    }
+
+    /// The index in [`HALF_LAYERS`] of each half-layer, from the top's first to the bottom's
+    /// second
+    fn half_layers(self) -> [usize; 4] {
+        let bits = self.as_bytes();
+        let mut halves = [0; 4];
+        let mut num_halves = 0;
+        let mut half_bits = 0;
+        let mut half_pieces = 0;
+        let mut half_size = 0;
+
+        for n in (0..16).rev() {
+            let is_big = (bits >> (BITS_PER_PIECE * n)) & 0b0010 != 0;
+            half_bits = (half_bits << 1) | is_big as u8;
+            half_pieces += 1;
+            half_size += 1 + is_big as u32;
+
+            if half_size == 6 {
+                halves[num_halves] = HALF_LAYERS
+                    .iter()
+                    .position(|&half| half == (half_bits, half_pieces))
+                    .unwrap();
+                num_halves += 1;
+                half_bits = 0;
+                half_pieces = 0;
+                half_size = 0;
+            }
+        }
+
+        assert_eq!(num_halves, 4, "the position cannot be sliced");
+        halves
+    }
+
+    /// The index from 0 to 7 of each big or small piece, in the order they appear
+    fn piece_indexes(self, big: bool) -> [u8; 8] {
+        let bits = self.as_bytes();
+        let mut indexes = [0; 8];
+        let mut num_indexes = 0;
+
+        for n in (0..16).rev() {
+            let piece = (bits >> (BITS_PER_PIECE * n)) & LAST_PIECE_MASK;
+            if (piece & 0b0010 != 0) == big {
+                // Drop the bit that tells the piece size
+                indexes[num_indexes] = (((piece >> 2) << 1) | (piece & 1)) as u8;
+                num_indexes += 1;
+            }
+        }
+
+        indexes
+    }
+}
+
+fn piece_from_index(index: u8, is_big: bool) -> u64 {
+    let index = index as u64;
+    ((index >> 1) << 2) | ((is_big as u64) << 1) | (index & 1)
+}
+
+fn unrank_shape(mut rank: u64) -> [usize; 4] {
+    let mut halves = [0; 4];
+    let mut num_big = 8;
+
+    for (n, half) in halves.iter_mut().enumerate() {
+        let remaining_halves = 3 - n;
+        for (candidate, &(candidate_bits, _)) in HALF_LAYERS.iter().enumerate() {
+            let candidate_big = candidate_bits.count_ones() as usize;
+            if candidate_big > num_big {
+                continue;
+            }
+
+            let completions = COMPLETIONS[remaining_halves][num_big - candidate_big];
+            if rank < completions {
+                *half = candidate;
+                num_big -= candidate_big;
+                break;
+            }
+            rank -= completions;
+        }
+    }
+
+    halves
+}
+
+/// Rank a permutation of the values from 0 to 7 in lexicographic order
+fn rank_permutation(values: [u8; 8]) -> u64 {
+    let mut rank = 0;
+    for i in 0..8 {
+        let smaller_after = values[i + 1..].iter().filter(|&&v| v < values[i]).count();
+        rank = rank * (8 - i as u64) + smaller_after as u64;
+    }
+    rank
+}
+
+fn unrank_permutation(mut rank: u64) -> [u8; 8] {
+    let mut digits = [0; 8];
+    for i in (0..8).rev() {
+        let base = 8 - i as u64;
+        digits[i] = (rank % base) as usize;
+        rank /= base;
+    }
+
+    let mut available = [0, 1, 2, 3, 4, 5, 6, 7];
+    let mut values = [0; 8];
+    for (i, digit) in digits.into_iter().enumerate() {
+        values[i] = available[digit];
+        available.copy_within(digit + 1.., digit);
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::NeighboursStack;
+
+    #[test]
+    fn num_shapes() {
+        assert_eq!(NUM_SHAPES, 3678);
+    }
+
+    #[test]
+    fn solved() {
+        let solved = Position::solved();
+        assert_eq!(Position::unrank(solved.rank()), solved);
+        assert_eq!(
+            Position::from_ranks(
+                solved.shape_rank(),
+                solved.big_permutation_rank(),
+                solved.small_permutation_rank()
+            ),
+            solved
+        );
+    }
+
+    #[test]
+    fn rank_and_unrank() {
+        let mut stack = NeighboursStack::new();
+        Position::solved().neighbours(&mut stack);
+
+        for movement in stack.neighbours() {
+            let position = movement.position();
+            assert!(position.rank() < NUM_POSITIONS);
+            assert_eq!(Position::unrank(position.rank()), position);
+            for middle_flipped in [false, true] {
+                let rank = position.rank_with_middle(middle_flipped);
+                assert!(rank < NUM_STATES);
+                assert_eq!(
+                    Position::unrank_with_middle(rank),
+                    (position, middle_flipped)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unrank_and_rank() {
+        // Every shape, with many permutations
+        for shape_rank in 0..NUM_SHAPES {
+            let permutation_rank = (shape_rank * 7919) % NUM_PERMUTATIONS;
+            let position = Position::from_ranks(
+                shape_rank,
+                permutation_rank,
+                NUM_PERMUTATIONS - 1 - permutation_rank,
+            );
+
+            assert_eq!(position.shape_rank(), shape_rank);
+            assert_eq!(position.big_permutation_rank(), permutation_rank);
+            assert_eq!(
+                position.small_permutation_rank(),
+                NUM_PERMUTATIONS - 1 - permutation_rank
+            );
+        }
+    }
+}
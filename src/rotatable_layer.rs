@@ -1,3 +1,4 @@
+use crate::move_tables;
 use crate::position::{BITS_PER_PIECE, LAST_PIECE_MASK};
 use crate::Piece;
 use std::fmt;
@@ -23,19 +24,17 @@ impl RotatableLayer {
     /// # Panics
     /// It will panic if the half-layers cannot be correctly constructed
     pub fn split(pieces: u64) -> (Self, Self) {
-        let (bottom_second, remaining) = Self::extract_right_most_half_layer(pieces).unwrap();
-        let (bottom_first, remaining) = Self::extract_right_most_half_layer(remaining).unwrap();
-        let (top_second, top_first) = Self::extract_right_most_half_layer(remaining).unwrap();
+        let [top_first, top_second, bottom_first, bottom_second] =
+            move_tables::split(pieces).expect("the position cannot be sliced");
+
+        let (bottom_second, remaining) = HalfLayer::take_right_most(pieces, bottom_second as u32);
+        let (bottom_first, remaining) = HalfLayer::take_right_most(remaining, bottom_first as u32);
+        let (top_second, remaining) = HalfLayer::take_right_most(remaining, top_second as u32);
+        let (top_first, _) = HalfLayer::take_right_most(remaining, top_first as u32);
 
         (
             RotatableLayer {
-                first: HalfLayer {
-                    pieces: top_first,
-                    num_pieces: 16
-                        - top_second.num_pieces
-                        - bottom_first.num_pieces
-                        - bottom_second.num_pieces,
-                },
+                first: top_first,
                 second: top_second,
             },
             RotatableLayer {
@@ -65,41 +64,33 @@ impl RotatableLayer {
 
         // Represent the layer as an `u64`, 4 bits per piece:
         // 0 ... 0 | first | second
-        let mut bits =
+        let bits =
             (self.first.pieces << (BITS_PER_PIECE * self.second.num_pieces)) | self.second.pieces;
-
-        // Count how many bits away from the right the left-most piece is
         let num_pieces = self.first.num_pieces + self.second.num_pieces;
-        let left_most_piece_shift = BITS_PER_PIECE * num_pieces - BITS_PER_PIECE;
 
         // Identity is always possible
         rotations.push((self, 0));
 
-        // Generate all other possible rotations
-        for n in 1..num_pieces {
-            // Take the right-most piece and push it into the left
-            let right_most_piece = bits & LAST_PIECE_MASK;
-            bits = (bits >> 4) | (right_most_piece << left_most_piece_shift);
-
-            // Split the bit pattern into each half-layer
-            match Self::extract_right_most_half_layer(bits) {
-                None => {
-                    // This is not a valid position, since no valid second half-layer can be
-                    // extracted
-                    continue;
-                }
-                Some((new_second_half, new_first_half)) => {
-                    let new_layer = RotatableLayer {
-                        first: HalfLayer {
-                            pieces: new_first_half,
-                            num_pieces: num_pieces - new_second_half.num_pieces,
-                        },
-                        second: new_second_half,
-                    };
-
-                    rotations.push((new_layer, n as u8));
-                }
-            }
+        // Generate all other possible rotations, which only depend on where the big pieces are
+        let layer_rotations = move_tables::layer_rotations(bits, num_pieces);
+        let mut valid = layer_rotations.valid & !1;
+        while valid != 0 {
+            let n = valid.trailing_zeros();
+            valid &= valid - 1;
+
+            let rotated = move_tables::rotate(bits, num_pieces, n);
+            let second_pieces = layer_rotations.second_pieces[n as usize] as u32;
+            let (second, first) = HalfLayer::take_right_most(rotated, second_pieces);
+            rotations.push((
+                RotatableLayer {
+                    first: HalfLayer {
+                        pieces: first,
+                        num_pieces: num_pieces - second_pieces,
+                    },
+                    second,
+                },
+                n as u8,
+            ));
         }
     }
 
@@ -115,39 +106,14 @@ impl RotatableLayer {
         bits |= bottom.second.pieces;
         bits
     }
+}
 
-    /// From a bit pattern, try to extract the right-most half-layer.
-    ///
-    /// This returns `None` if it's not possible to do it because a big piece would need to be
-    /// split into two half-layers.
-    fn extract_right_most_half_layer(bits: u64) -> Option<(HalfLayer, u64)> {
-        // Check if the pattern can finish with a half-layer by counting how many big pieces there
-        // are in the right-most positions. A half-layer can be made of either:
-        // - 3 big pieces
-        // - 2 big and 2 small
-        // - 1 big and 4 small
-        // - 6 small pieces
-        // Each piece is 4 bits, and the mask 0b0010 reveals whether it's a big piece.
-        const MASK_LAST_3_SIZES: u64 = 0x222;
-        const MASK_LAST_4_SIZES: u64 = 0x2222;
-        const MASK_LAST_5_SIZES: u64 = 0x2_2222;
-        const MASK_LAST_6_SIZES: u64 = 0x22_2222;
-
-        let num_pieces = if bits & MASK_LAST_3_SIZES == MASK_LAST_3_SIZES {
-            3
-        } else if (bits & MASK_LAST_4_SIZES).count_ones() == 2 {
-            4
-        } else if (bits & MASK_LAST_5_SIZES).count_ones() == 1 {
-            5
-        } else if bits & MASK_LAST_6_SIZES == 0 {
-            6
-        } else {
-            return None;
-        };
-
+impl HalfLayer {
+    /// Split the last `num_pieces` from the bit pattern, returning them and the remaining bits
+    fn take_right_most(bits: u64, num_pieces: u32) -> (Self, u64) {
         let remaining_bits = bits >> (BITS_PER_PIECE * num_pieces);
         let pieces = bits ^ (remaining_bits << (BITS_PER_PIECE * num_pieces));
-        Some((HalfLayer { pieces, num_pieces }, remaining_bits))
+        (HalfLayer { pieces, num_pieces }, remaining_bits)
     }
 }
 
@@ -185,11 +151,13 @@ mod tests {
             bits |= piece.as_bits();
         }
 
-        let (second, first) = RotatableLayer::extract_right_most_half_layer(bits).unwrap();
+        let num_pieces = pieces.len() as u32;
+        let second_pieces = move_tables::layer_rotations(bits, num_pieces).second_pieces[0] as u32;
+        let (second, first) = HalfLayer::take_right_most(bits, second_pieces);
         RotatableLayer {
             first: HalfLayer {
                 pieces: first,
-                num_pieces: pieces.len() as u32 - second.num_pieces,
+                num_pieces: num_pieces - second_pieces,
             },
             second,
         }
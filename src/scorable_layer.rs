@@ -1,4 +1,6 @@
-use crate::position::{BITS_PER_PIECE, LAST_PIECE_MASK};
+use crate::move_tables;
+use crate::position::BITS_PER_PIECE;
+use crate::Position;
 
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
 pub struct ScorableLayer {
@@ -6,39 +8,21 @@ pub struct ScorableLayer {
     num_pieces: u32,
 }
 
+/// The lowest bit of each group of 4 bits
+const LOW_BITS: u64 = 0x1111_1111_1111_1111;
+
+/// The number of positions scored together by [`score_batch()`]
+const LANES: usize = 8;
+
 impl ScorableLayer {
     /// Split a full position into its two layers
     ///
     /// # Panics
     /// It will panic if the layers cannot be correctly constructed
     pub fn split(pieces: u64) -> (Self, Self) {
-        // Check if the pattern can finish with a layer by counting how many big pieces there
-        // are in the right-most positions. A layer can be made of either:
-        // - 6 big pieces
-        // - 5 big and 2 small
-        // - 4 big and 4 small
-        // - 3 big and 6 small
-        // - 2 big and 8 small
-        // Each piece is 4 bits, and the mask 0b0010 reveals whether it's a big piece.
-        const MASK_LAST_6_SIZES: u64 = 0x22_2222;
-        const MASK_LAST_7_SIZES: u64 = 0x222_2222;
-        const MASK_LAST_8_SIZES: u64 = 0x2222_2222;
-        const MASK_LAST_9_SIZES: u64 = 0x2_2222_2222;
-        const MASK_LAST_10_SIZES: u64 = 0x22_2222_2222;
-
-        let bottom_num_pieces = if pieces & MASK_LAST_6_SIZES == MASK_LAST_6_SIZES {
-            6
-        } else if (pieces & MASK_LAST_7_SIZES).count_ones() == 5 {
-            7
-        } else if (pieces & MASK_LAST_8_SIZES).count_ones() == 4 {
-            8
-        } else if (pieces & MASK_LAST_9_SIZES).count_ones() == 3 {
-            9
-        } else if (pieces & MASK_LAST_10_SIZES).count_ones() == 2 {
-            10
-        } else {
-            unreachable!()
-        };
+        let [_, _, bottom_first, bottom_second] =
+            move_tables::split(pieces).expect("the position cannot be sliced");
+        let bottom_num_pieces = (bottom_first + bottom_second) as u32;
 
         let top_pieces = pieces >> (BITS_PER_PIECE * bottom_num_pieces);
         let bottom_pieces = pieces ^ (top_pieces << (BITS_PER_PIECE * bottom_num_pieces));
@@ -54,17 +38,83 @@ impl ScorableLayer {
         )
     }
 
+    /// Count the pieces that are followed by their successor, going around the layer.
+    ///
+    /// Each piece has 4 bits, the 3 MSB represent a circular sequence number of a given external
+    /// color (white/yellow), so the successor of a piece is 2 more, modulo 16. Every pair of
+    /// neighbours is compared at once, by aligning each piece with the one before it.
     pub fn score(self) -> u8 {
+        let width = BITS_PER_PIECE * self.num_pieces;
+        let layer_mask = (1 << width) - 1;
+        let pieces = self.pieces;
+
+        // Rotate by one piece, so that each piece is aligned with the one before it
+        let previous =
+            ((pieces >> BITS_PER_PIECE) | (pieces << (width - BITS_PER_PIECE))) & layer_mask;
+
+        // Add 2 to each group of 4 bits. The lower 3 bits can't overflow past the group, and the
+        // highest bit is added back without carry
+        let high_bits = LOW_BITS << 3;
+        let successors =
+            (((previous & !high_bits) + (LOW_BITS << 1)) ^ (previous & high_bits)) & layer_mask;
+
+        // A group is zero where the piece is the successor of the one before it
+        let mut differences = pieces ^ successors;
+        differences |= differences >> 1;
+        differences |= differences >> 2;
+        let num_different = (differences & LOW_BITS & layer_mask).count_ones();
+
+        (self.num_pieces - num_different) as u8
+    }
+}
+
+/// Score every position, writing the scores in the same order. The layers of [`LANES`] positions are split first, then scored together: each
+/// score has no branch and doesn't depend on the others, so that the compiler can interleave or
+/// vectorize them.
+pub fn score_batch(positions: &[Position], scores: &mut Vec<u8>) {
+    scores.clear();
+    let chunks = positions.chunks_exact(LANES);
+    let remainder = chunks.remainder();
+
+    let empty = ScorableLayer {
+        pieces: 0,
+        num_pieces: 1,
+    };
+    for chunk in chunks {
+        let mut tops = [empty; LANES];
+        let mut bottoms = [empty; LANES];
+        for (i, position) in chunk.iter().enumerate() {
+            let (top, bottom) = ScorableLayer::split(position.as_bytes());
+            tops[i] = top;
+            bottoms[i] = bottom;
+        }
+
+        let mut chunk_scores = [0; LANES];
+        for i in 0..LANES {
+            chunk_scores[i] = tops[i].score() + bottoms[i].score();
+        }
+        scores.extend_from_slice(&chunk_scores);
+    }
+
+    scores.extend(remainder.iter().map(|position| position.score()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::{NeighboursStack, LAST_PIECE_MASK};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    /// The reference implementation, which compares the pieces one by one
+    fn scalar_score(layer: ScorableLayer) -> u8 {
         let mut score = 0;
-        let mut bits = self.pieces;
-        let left_most_piece_shift = BITS_PER_PIECE * self.num_pieces - BITS_PER_PIECE;
+        let mut bits = layer.pieces;
+        let left_most_piece_shift = BITS_PER_PIECE * layer.num_pieces - BITS_PER_PIECE;
 
-        for _ in 0..self.num_pieces {
+        for _ in 0..layer.num_pieces {
             let last_piece = bits & LAST_PIECE_MASK;
             let second_last_piece = (bits >> BITS_PER_PIECE) & LAST_PIECE_MASK;
-
-            // Each piece has 4 bits, the 3 MSB represent a circular sequence number of a given
-            // external color (white/yellow).
             let successor_second_last_piece = (second_last_piece + 0b0010) & LAST_PIECE_MASK;
 
             score += (last_piece == successor_second_last_piece) as u8;
@@ -74,4 +124,46 @@ impl ScorableLayer {
 
         score
     }
+
+    proptest! {
+        /// Any pieces, even if they are not part of a valid position. A layer of 12 units has
+        /// from 6 big pieces to 8 small ones and 2 big ones.
+        #[test]
+        fn same_as_scalar(pieces: u64, num_pieces in 6u32..=10) {
+            let layer = ScorableLayer {
+                pieces: pieces & ((1 << (BITS_PER_PIECE * num_pieces)) - 1),
+                num_pieces,
+            };
+            prop_assert_eq!(layer.score(), scalar_score(layer));
+        }
+
+        /// The positions along a random walk, and all the neighbours of the last one, scored one by
+        /// one and in a batch
+        #[test]
+        fn positions_same_as_scalar(walk in vec(any::<usize>(), 0..50)) {
+            let mut stack = NeighboursStack::new();
+            let mut position = Position::solved();
+            for step in walk {
+                position.neighbours(&mut stack);
+                position = stack.neighbours()[step % stack.neighbours().len()].position();
+            }
+
+            position.neighbours(&mut stack);
+            let positions = stack
+                .neighbours()
+                .iter()
+                .map(|m| m.position())
+                .chain([position])
+                .collect::<Vec<_>>();
+            let mut scores = vec![];
+            score_batch(&positions, &mut scores);
+            prop_assert_eq!(scores.len(), positions.len());
+
+            for (position, score) in positions.into_iter().zip(scores) {
+                let (top, bottom) = ScorableLayer::split(position.as_bytes());
+                prop_assert_eq!(position.score(), scalar_score(top) + scalar_score(bottom));
+                prop_assert_eq!(score, position.score());
+            }
+        }
+    }
 }
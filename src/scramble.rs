@@ -0,0 +1,92 @@
+//! Reproducible scrambles, as random walks from the solved position.
+
+use crate::position::{Movement, NeighboursStack};
+use crate::Position;
+use std::ops::ControlFlow;
+
+/// A xorshift generator, which is more than enough to pick movements
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    /// Any seed is valid: it's mixed first, so that close seeds give unrelated sequences and the
+    /// state is never zero
+    pub fn new(seed: u64) -> Self {
+        let mut mixed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        mixed ^= mixed >> 31;
+        XorShift {
+            state: if mixed == 0 { 1 } else { mixed },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A number in `0..n`, with a negligible bias for small `n`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Walk `length` random movements from the solved position. The first movement has an empty change
+/// and holds the solved position, like in [`crate::find_solution::Solution::movements()`], so the
+/// scrambled position is the last one.
+///
+/// Each movement is picked among the ones of [`Position::visit_neighbours()`], so that no movement
+/// only rotates the layers or undoes the flip of the previous one. The same seed always gives the
+/// same scramble.
+pub fn scramble(seed: u64, length: usize) -> Vec<Movement> {
+    let mut rng = XorShift::new(seed);
+    let mut stack = NeighboursStack::new();
+    let mut candidates = vec![];
+    let mut movements = vec![Movement::initial_movement(Position::solved())];
+
+    for n in 0..length {
+        candidates.clear();
+        let position = movements[movements.len() - 1].position();
+        let _ = position.visit_neighbours(&mut stack, n > 0, |movement| {
+            candidates.push(movement);
+            ControlFlow::Continue(())
+        });
+        movements.push(candidates[rng.below(candidates.len())]);
+    }
+
+    movements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproducible() {
+        let movements = scramble(42, 20);
+        assert_eq!(movements.len(), 21);
+        assert_eq!(movements[0].position(), Position::solved());
+
+        for pair in movements.windows(2) {
+            assert_eq!(
+                pair[0].position().apply(pair[1].change()),
+                Some(pair[1].position())
+            );
+        }
+
+        let positions = |movements: Vec<Movement>| {
+            movements
+                .iter()
+                .map(|movement| movement.position())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(positions(scramble(42, 20)), positions(movements.clone()));
+        assert_ne!(positions(scramble(43, 20)), positions(movements));
+        assert_eq!(scramble(0, 0).len(), 1);
+    }
+}
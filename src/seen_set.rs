@@ -0,0 +1,122 @@
+use crate::bloom_set::BloomSet;
+#[cfg(unix)]
+use crate::disk_set::DiskSet;
+use crate::sharded_set::ShardedSet;
+use anyhow::Result;
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+/// The positions that were already reached by the search, with the smallest depth at which each
+/// one was reached.
+///
+/// Inserting fails if the storage of the set cannot be accessed, like the files of a [`DiskSet`].
+pub trait SeenSet: Debug + Send + Sync {
+    /// Insert the value if it's not yet present, returning whether it was inserted
+    fn insert(&self, value: u64, depth: u16) -> Result<bool>;
+
+    /// Insert the value if it's not yet present or if it was inserted with a greater depth,
+    /// returning whether it was inserted
+    fn insert_if_shallower(&self, value: u64, depth: u16) -> Result<bool>;
+
+    /// Insert the values like [`SeenSet::insert()`], or like [`SeenSet::insert_if_shallower()`]
+    /// with `replace_deeper`, returning whether each one was inserted. When a value appears
+    /// several times, only its first occurrence at its smallest depth can be inserted.
+    fn insert_batch(&self, entries: &[(u64, u16)], replace_deeper: bool) -> Result<Vec<bool>> {
+        let mut order = (0..entries.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| entries[i]);
+        let mut inserted = vec![false; entries.len()];
+        for i in order {
+            let (value, depth) = entries[i];
+            inserted[i] = if replace_deeper {
+                self.insert_if_shallower(value, depth)?
+            } else {
+                self.insert(value, depth)?
+            };
+        }
+        Ok(inserted)
+    }
+
+    /// The number of values worth inserting at once with [`SeenSet::insert_batch()`]. The search
+    /// checks its new nodes in batches of this size, which delays their exploration.
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether a value can be wrongly considered present, in which case the search may miss
+    /// shorter solutions
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    /// The estimated probability that a new value is considered present
+    fn false_positive_rate(&self) -> Option<f64> {
+        None
+    }
+
+    /// The estimated number of insertions that were rejected because of false positives
+    fn false_rejections(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Where the seen positions are kept
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SeenStorage {
+    /// In a [`ShardedSet`], which is the fastest as long as it fits in memory
+    Memory,
+    /// In a [`DiskSet`] inside `directory`, keeping at most `memory_bytes` of recent positions in
+    /// memory. Only the seen positions go to the disk: the search still keeps about 8 bytes per
+    /// node to rebuild the paths, and 16 bytes per node waiting in the queues, in memory.
+    Disk {
+        directory: PathBuf,
+        memory_bytes: usize,
+    },
+    /// In a [`BloomSet`] of `memory_bytes`, including the sample that estimates its false
+    /// rejections, which may reject new positions. Since depths are not stored, positions reached
+    /// again by a shorter path are not explored again.
+    Bloom {
+        memory_bytes: usize,
+        num_hashes: u32,
+    },
+}
+
+impl SeenStorage {
+    pub fn create(&self) -> Result<Box<dyn SeenSet>> {
+        Ok(match self {
+            SeenStorage::Memory => Box::new(ShardedSet::new()),
+            #[cfg(unix)]
+            SeenStorage::Disk {
+                directory,
+                memory_bytes,
+            } => Box::new(DiskSet::new(directory, *memory_bytes)?),
+            #[cfg(not(unix))]
+            SeenStorage::Disk { .. } => {
+                anyhow::bail!("the seen positions can only be kept on the disk on Unix")
+            }
+            SeenStorage::Bloom {
+                memory_bytes,
+                num_hashes,
+            } => Box::new(BloomSet::new(*memory_bytes, *num_hashes)),
+        })
+    }
+}
+
+impl SeenSet for ShardedSet {
+    fn insert(&self, value: u64, depth: u16) -> Result<bool> {
+        Ok(ShardedSet::insert(self, value, depth))
+    }
+
+    fn insert_if_shallower(&self, value: u64, depth: u16) -> Result<bool> {
+        Ok(ShardedSet::insert_if_shallower(self, value, depth))
+    }
+
+    fn len(&self) -> usize {
+        ShardedSet::len(self)
+    }
+}
@@ -0,0 +1,323 @@
+//! The shape of the puzzle: which pieces are big, regardless of which pieces they are.
+//!
+//! Each layer has a [`LayerShape`], which doesn't depend on how the layer is rotated. There are
+//! 29 of them, which form the 170 [`Shape`] that can be reached. Turning the puzzle upside down
+//! exchanges the layers and mirrors them, so they are usually grouped into a catalogue of 90.
+
+use crate::move_tables;
+use crate::position::{Movement, NeighboursStack};
+use crate::Position;
+use itertools::Itertools;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fmt::Write;
+use std::ops::ControlFlow;
+
+/// The pattern of big and small pieces of a layer, up to rotation
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct LayerShape {
+    /// One bit per piece, set for big pieces, with the first piece in the most significant bit.
+    /// It's the smallest value among the rotations of the layer.
+    mask: u16,
+    num_pieces: u8,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Shape {
+    top: LayerShape,
+    bottom: LayerShape,
+}
+
+/// The distance of every reachable shape to [`Shape::square()`], in compound movements like
+/// [`crate::position::Change`]
+#[derive(Debug, Clone)]
+pub struct ShapeTable {
+    distances: HashMap<Shape, u8>,
+}
+
+/// The usual names of the layers with 4 or 6 big pieces, by the number of small pieces between
+/// consecutive big ones, see [`LayerShape::edge_gaps()`]. Left and right follow the order of the
+/// pieces in [`Position::pieces()`].
+const NAMES: [(&[u8], &str); 11] = [
+    (&[0, 0, 0, 0, 0, 0], "Star"),
+    (&[1, 1, 1, 1], "Square"),
+    (&[2, 2, 0, 0], "Kite"),
+    (&[2, 0, 2, 0], "Barrel"),
+    (&[2, 1, 0, 1], "Shield"),
+    (&[3, 0, 1, 0], "Mushroom"),
+    (&[4, 0, 0, 0], "Scallop"),
+    (&[3, 1, 0, 0], "Left Fist"),
+    (&[3, 0, 0, 1], "Right Fist"),
+    (&[2, 1, 1, 0], "Left Paw"),
+    (&[2, 0, 1, 1], "Right Paw"),
+];
+
+impl LayerShape {
+    fn new(mask: u16, num_pieces: u32) -> Self {
+        let full = (1 << num_pieces) - 1;
+        let mask = (0..num_pieces)
+            .map(|n| ((mask >> n) | (mask << (num_pieces - n))) & full)
+            .min()
+            .unwrap_or(0);
+        LayerShape {
+            mask,
+            num_pieces: num_pieces as u8,
+        }
+    }
+
+    pub fn num_big_pieces(self) -> u32 {
+        self.mask.count_ones()
+    }
+
+    pub fn num_small_pieces(self) -> u32 {
+        self.num_pieces as u32 - self.num_big_pieces()
+    }
+
+    /// The number of small pieces after each big piece, starting from the big piece that makes
+    /// the sequence the largest
+    pub fn edge_gaps(self) -> Vec<u8> {
+        let num_pieces = self.num_pieces as u32;
+        let is_big = |n: u32| (self.mask >> (num_pieces - 1 - n % num_pieces)) & 1 == 1;
+
+        let big_pieces = (0..num_pieces).filter(|&n| is_big(n)).collect_vec();
+        let gaps = big_pieces
+            .iter()
+            .map(|&start| (start + 1..).take_while(|&n| !is_big(n)).count() as u8)
+            .collect_vec();
+
+        (0..gaps.len())
+            .map(|n| gaps[n..].iter().chain(&gaps[..n]).copied().collect_vec())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// The usual name of the layer if it has one, otherwise its [`LayerShape::edge_gaps()`]
+    pub fn name(self) -> String {
+        let gaps = self.edge_gaps();
+        match NAMES.iter().find(|(name_gaps, _)| *name_gaps == gaps) {
+            Some((_, name)) => name.to_string(),
+            None => gaps.iter().join("-"),
+        }
+    }
+
+    /// The same layer seen from the other side, which reverses the order of the pieces
+    fn mirrored(self) -> Self {
+        let num_pieces = self.num_pieces as u32;
+        let reversed = self.mask.reverse_bits() >> (u16::BITS - num_pieces);
+        LayerShape::new(reversed, num_pieces)
+    }
+}
+
+impl Shape {
+    /// The shape of the solved position, where both layers are squares
+    pub fn square() -> Self {
+        Position::solved().shape()
+    }
+
+    pub fn top(self) -> LayerShape {
+        self.top
+    }
+
+    pub fn bottom(self) -> LayerShape {
+        self.bottom
+    }
+
+    /// The shape after turning the puzzle upside down, see
+    /// [`crate::symmetry::Symmetry::UpsideDown`]
+    pub fn upside_down(self) -> Self {
+        Shape {
+            top: self.bottom.mirrored(),
+            bottom: self.top.mirrored(),
+        }
+    }
+
+    /// The shape of the catalogue of 90 shapes: the smallest of this one and the one upside down
+    pub fn catalogue_shape(self) -> Self {
+        self.min(self.upside_down())
+    }
+
+    /// Every shape that can be reached from the solved position, with a position that has each
+    /// one, in the order they are found
+    pub fn all() -> Vec<(Shape, Position)> {
+        let mut stack = NeighboursStack::new();
+        let mut found = vec![(Shape::square(), Position::solved())];
+        let mut known: HashSet<Shape> = found.iter().map(|&(shape, _)| shape).collect();
+
+        let mut next = 0;
+        while next < found.len() {
+            let (_, position) = found[next];
+            next += 1;
+
+            let _ = position.visit_neighbours(&mut stack, false, |movement| {
+                let shape = movement.position().shape();
+                if known.insert(shape) {
+                    found.push((shape, movement.position()));
+                }
+                ControlFlow::Continue(())
+            });
+        }
+
+        found
+    }
+}
+
+impl ShapeTable {
+    /// Compute the distances with a breadth-first search from [`Shape::square()`]. Every movement
+    /// can be undone by another one, so the distance from the square is also the distance to it.
+    pub fn new() -> Self {
+        let mut stack = NeighboursStack::new();
+        let mut distances = HashMap::new();
+        distances.insert(Shape::square(), 0);
+
+        let mut queue = VecDeque::from(vec![(Position::solved(), 0)]);
+        while let Some((position, distance)) = queue.pop_front() {
+            let _ = position.visit_neighbours(&mut stack, false, |movement| {
+                let shape = movement.position().shape();
+                if let Entry::Vacant(entry) = distances.entry(shape) {
+                    entry.insert(distance + 1);
+                    queue.push_back((movement.position(), distance + 1));
+                }
+                ControlFlow::Continue(())
+            });
+        }
+
+        ShapeTable { distances }
+    }
+
+    /// The smallest number of movements to make both layers squares, or `None` if the shape
+    /// can't be reached
+    pub fn distance(&self, shape: Shape) -> Option<u8> {
+        self.distances.get(&shape).copied()
+    }
+
+    pub fn shapes(&self) -> impl Iterator<Item = (Shape, u8)> + '_ {
+        self.distances
+            .iter()
+            .map(|(&shape, &distance)| (shape, distance))
+    }
+
+    /// A movement that gets one step closer to [`Shape::square()`], or `None` if the layers are
+    /// already squares
+    pub fn next_movement(&self, position: Position) -> Option<Movement> {
+        let distance = self.distance(position.shape())?;
+        if distance == 0 {
+            return None;
+        }
+
+        let mut stack = NeighboursStack::new();
+        let mut best = None;
+        let _ = position.visit_neighbours(&mut stack, false, |movement| {
+            if self.distance(movement.position().shape()) == Some(distance - 1) {
+                best = Some(movement);
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        best
+    }
+}
+
+impl Default for ShapeTable {
+    fn default() -> Self {
+        ShapeTable::new()
+    }
+}
+
+impl Position {
+    pub fn shape(self) -> Shape {
+        let bits = self.as_bytes();
+        let [_, _, bottom_first, bottom_second] =
+            move_tables::split(bits).expect("the position cannot be sliced");
+        let bottom_num_pieces = (bottom_first + bottom_second) as u32;
+        let mask = move_tables::big_pieces_mask(bits) as u16;
+
+        Shape {
+            top: LayerShape::new(mask >> bottom_num_pieces, 16 - bottom_num_pieces),
+            bottom: LayerShape::new(mask & ((1 << bottom_num_pieces) - 1), bottom_num_pieces),
+        }
+    }
+}
+
+impl fmt::Display for LayerShape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.top)?;
+        f.write_char('/')?;
+        write!(f, "{}", self.bottom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn catalogue() {
+        let shapes = Shape::all();
+        assert_eq!(shapes.len(), 170);
+        assert_eq!(shapes[0].0.to_string(), "Square/Square");
+
+        let layers: BTreeSet<_> = shapes
+            .iter()
+            .flat_map(|(shape, _)| [shape.top(), shape.bottom()])
+            .collect();
+        assert_eq!(layers.len(), 29);
+        assert_eq!(layers.iter().map(|layer| layer.name()).unique().count(), 29);
+
+        let catalogue: BTreeSet<_> = shapes
+            .iter()
+            .map(|(shape, _)| shape.catalogue_shape())
+            .collect();
+        assert_eq!(catalogue.len(), 90);
+
+        for (shape, position) in shapes {
+            assert_eq!(position.shape(), shape);
+            assert_eq!(
+                shape.top().num_big_pieces() + shape.bottom().num_big_pieces(),
+                8
+            );
+        }
+    }
+
+    #[test]
+    fn names() {
+        let star = LayerShape::new(0b11_1111, 6);
+        assert_eq!(star.name(), "Star");
+        assert_eq!(star.edge_gaps(), vec![0; 6]);
+
+        // 4 big pieces then 4 small ones, in any rotation
+        let scallop = LayerShape::new(0b0011_1100, 8);
+        assert_eq!(scallop.name(), "Scallop");
+        assert_eq!(LayerShape::new(0b1000_1011, 8).name(), "Left Fist");
+        assert_eq!(
+            LayerShape::new(0b1000_1011, 8).mirrored().name(),
+            "Right Fist"
+        );
+        assert_eq!(LayerShape::new(0b11, 10).name(), "8-0");
+    }
+
+    #[test]
+    fn shape_table() {
+        let table = ShapeTable::new();
+        assert_eq!(table.shapes().count(), 170);
+        assert_eq!(table.distance(Shape::square()), Some(0));
+
+        // Following the movements of the table reaches the square in the expected number of steps
+        for (shape, mut position) in Shape::all() {
+            let distance = table.distance(shape).unwrap();
+            for _ in 0..distance {
+                position = table.next_movement(position).unwrap().position();
+            }
+            assert_eq!(position.shape(), Shape::square());
+            assert!(table.next_movement(position).is_none());
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use parking_lot::RwLock;
+use std::mem;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Represent a set of [`u64`], shared by many threads. Each value is stored with the smallest depth
+/// at which it was inserted.
+///
+/// The values are mixed and split into a 16-bit prefix, which selects one of the shards, and a
+/// 48-bit suffix, which is stored with the depth in a single [`AtomicU64`] of an open addressing
+/// table. Inserting only takes a shared lock on the shard: slots are claimed with a
+/// compare-and-swap, and the exclusive lock is only needed to grow the table.
+///
+/// The tables aren't sized in powers of two: they grow by a quarter and are filled up to 85%, so
+/// that a slot of 8 bytes takes about 11 bytes per value on average, rather than the 16 of tables
+/// that double at 75%.
+#[derive(Debug)]
+pub struct ShardedSet {
+    shards: Vec<RwLock<Shard>>,
+}
+
+#[derive(Debug)]
+struct Shard {
+    /// Each slot is `suffix << DEPTH_BITS | (depth + 1)`, or [`EMPTY`]
+    slots: Box<[AtomicU64]>,
+    len: AtomicUsize,
+}
+
+enum Outcome {
+    Inserted { should_grow: bool },
+    Updated,
+    Present,
+    Full,
+}
+
+const PREFIX_BITS: u32 = 16;
+
+/// The prefix is implicit, which leaves as many bits next to the suffix
+const DEPTH_BITS: u32 = PREFIX_BITS;
+
+const DEPTH_MASK: u64 = (1 << DEPTH_BITS) - 1;
+
+const EMPTY: u64 = 0;
+
+/// The size of the table of a shard at its first value, small enough to not waste memory on
+/// shards that stay almost empty
+const INITIAL_SLOTS: usize = 16;
+
+/// Linear probing degrades quickly above this load factor
+const MAX_LOAD_PERCENT: usize = 85;
+
+impl ShardedSet {
+    /// An empty set, where the shards have no table until their first value, so that it only
+    /// takes [`ShardedSet::memory_bytes()`] for the shards themselves
+    pub fn new() -> Self {
+        ShardedSet {
+            shards: (0..2usize.pow(PREFIX_BITS))
+                .map(|_| RwLock::new(Shard::with_slots(0)))
+                .collect(),
+        }
+    }
+
+    /// Insert the value if it's not yet present, returning whether it was inserted
+    pub fn insert(&self, value: u64, depth: u16) -> bool {
+        self.insert_with(value, depth, false)
+    }
+
+    /// Insert the value if it's not yet present or if it was inserted with a greater depth,
+    /// returning whether it was inserted
+    pub fn insert_if_shallower(&self, value: u64, depth: u16) -> bool {
+        self.insert_with(value, depth, true)
+    }
+
+    /// The depth at which the value was inserted, if it's present
+    pub fn depth(&self, value: u64) -> Option<u16> {
+        let (prefix, suffix) = split(value);
+        let shard = self.shards[prefix].read();
+        let num_slots = shard.slots.len();
+
+        let mut index = home(suffix, num_slots);
+        for _ in 0..num_slots {
+            let entry = shard.slots[index].load(Ordering::Acquire);
+            if entry == EMPTY {
+                return None;
+            } else if entry >> DEPTH_BITS == suffix {
+                return Some(((entry & DEPTH_MASK) - 1) as u16);
+            }
+            index = next(index, num_slots);
+        }
+        None
+    }
+
+    /// Every value with its depth, in no particular order
+    pub fn entries(&self) -> Vec<(u64, u16)> {
+        let mut entries = Vec::with_capacity(self.len());
+        for (prefix, shard) in self.shards.iter().enumerate() {
+            let shard = shard.read();
+            entries.extend(
+                shard
+                    .slots
+                    .iter()
+                    .map(|slot| slot.load(Ordering::Acquire))
+                    .filter(|&entry| entry != EMPTY)
+                    .map(|entry| {
+                        let suffix = entry >> DEPTH_BITS;
+                        let value = unstir(((prefix as u64) << (u64::BITS - PREFIX_BITS)) | suffix);
+                        (value, ((entry & DEPTH_MASK) - 1) as u16)
+                    }),
+            );
+        }
+        entries
+    }
+
+    /// Remove every value, keeping the tables for the next ones
+    pub fn clear(&mut self) {
+        for shard in &mut self.shards {
+            let shard = shard.get_mut();
+            for slot in shard.slots.iter_mut() {
+                *slot.get_mut() = EMPTY;
+            }
+            *shard.len.get_mut() = 0;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().len.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// The memory used by the tables, not counting the allocator overhead
+    pub fn memory_bytes(&self) -> usize {
+        let slots: usize = self
+            .shards
+            .iter()
+            .map(|shard| shard.read().slots.len())
+            .sum();
+        slots * mem::size_of::<AtomicU64>() + self.shards.len() * mem::size_of::<RwLock<Shard>>()
+    }
+
+    fn insert_with(&self, value: u64, depth: u16, replace_deeper: bool) -> bool {
+        let (prefix, suffix) = split(value);
+        let shard = &self.shards[prefix];
+
+        loop {
+            let outcome = shard.read().insert(suffix, depth, replace_deeper);
+            match outcome {
+                Outcome::Inserted { should_grow } => {
+                    if should_grow {
+                        shard.write().grow_if_loaded();
+                    }
+                    return true;
+                }
+                Outcome::Updated => return true,
+                Outcome::Present => return false,
+                // Other threads filled the table before it could grow
+                Outcome::Full => shard.write().grow_if_loaded(),
+            }
+        }
+    }
+}
+
+impl Default for ShardedSet {
+    fn default() -> Self {
+        ShardedSet::new()
+    }
+}
+
+impl Shard {
+    fn with_slots(num_slots: usize) -> Self {
+        Shard {
+            slots: (0..num_slots).map(|_| AtomicU64::new(EMPTY)).collect(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn insert(&self, suffix: u64, depth: u16, replace_deeper: bool) -> Outcome {
+        let num_slots = self.slots.len();
+        if num_slots == 0 {
+            return Outcome::Full;
+        }
+
+        // Depths are offset by one so that an empty slot can't be confused with a value
+        let entry = (suffix << DEPTH_BITS) | (depth as u64 + 1).min(DEPTH_MASK);
+
+        let mut index = home(suffix, num_slots);
+        for _ in 0..num_slots {
+            let slot = &self.slots[index];
+            let mut current = slot.load(Ordering::Acquire);
+
+            loop {
+                if current == EMPTY {
+                    match slot.compare_exchange_weak(
+                        EMPTY,
+                        entry,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    ) {
+                        Ok(_) => {
+                            let len = self.len.fetch_add(1, Ordering::Relaxed) + 1;
+                            return Outcome::Inserted {
+                                should_grow: is_loaded(len, num_slots),
+                            };
+                        }
+                        Err(actual) => current = actual,
+                    }
+                } else if current >> DEPTH_BITS == suffix {
+                    if !replace_deeper || current & DEPTH_MASK <= entry & DEPTH_MASK {
+                        return Outcome::Present;
+                    }
+                    match slot.compare_exchange_weak(
+                        current,
+                        entry,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    ) {
+                        Ok(_) => return Outcome::Updated,
+                        Err(actual) => current = actual,
+                    }
+                } else {
+                    break;
+                }
+            }
+
+            index = next(index, num_slots);
+        }
+
+        Outcome::Full
+    }
+
+    /// Add a quarter to the number of slots, or create the table of an empty shard, unless
+    /// another thread already did it
+    fn grow_if_loaded(&mut self) {
+        let len = *self.len.get_mut();
+        let num_slots = self.slots.len();
+        if num_slots > 0 && !is_loaded(len, num_slots) {
+            return;
+        }
+
+        let grown = Shard::with_slots((num_slots + num_slots / 4).max(INITIAL_SLOTS));
+        let grown_slots = grown.slots.len();
+        for slot in self.slots.iter_mut() {
+            let entry = *slot.get_mut();
+            if entry == EMPTY {
+                continue;
+            }
+
+            let mut index = home(entry >> DEPTH_BITS, grown_slots);
+            while grown.slots[index].load(Ordering::Relaxed) != EMPTY {
+                index = next(index, grown_slots);
+            }
+            grown.slots[index].store(entry, Ordering::Relaxed);
+        }
+
+        grown.len.store(len, Ordering::Relaxed);
+        *self = grown;
+    }
+}
+
+fn is_loaded(len: usize, num_slots: usize) -> bool {
+    100 * len > MAX_LOAD_PERCENT * num_slots
+}
+
+/// The first slot to probe for a suffix, which spreads the suffixes over a table of any size by
+/// their high bits, without a division
+fn home(suffix: u64, num_slots: usize) -> usize {
+    ((suffix as u128 * num_slots as u128) >> (u64::BITS - PREFIX_BITS)) as usize
+}
+
+fn next(index: usize, num_slots: usize) -> usize {
+    if index + 1 == num_slots {
+        0
+    } else {
+        index + 1
+    }
+}
+
+/// The mixing is a bijection, so the value is stored exactly even though the prefix is implicit
+fn split(value: u64) -> (usize, u64) {
+    let value = stir(value);
+    let prefix = value >> (u64::BITS - PREFIX_BITS);
+    let suffix = value ^ (prefix << (u64::BITS - PREFIX_BITS));
+    (prefix as usize, suffix)
+}
+
+fn stir(v: u64) -> u64 {
+    let [mut a, mut b, mut c, mut d] = unsafe { mem::transmute::<u64, [u16; 4]>(v) };
+
+    for _ in 0..4 {
+        // Slightly inspired by the core of SHA-1
+        let new_a = (b ^ c).wrapping_add(d).wrapping_add(a.rotate_left(5));
+        let new_b = a;
+        let new_c = b.rotate_left(14);
+        let new_d = c;
+
+        a = new_a;
+        b = new_b;
+        c = new_c;
+        d = new_d;
+    }
+
+    unsafe { mem::transmute::<[u16; 4], u64>([a, b, c, d]) }
+}
+
+/// The inverse of [`stir()`]
+fn unstir(v: u64) -> u64 {
+    let [mut a, mut b, mut c, mut d] = unsafe { mem::transmute::<u64, [u16; 4]>(v) };
+
+    for _ in 0..4 {
+        let old_a = b;
+        let old_b = c.rotate_right(14);
+        let old_c = d;
+        let old_d = a
+            .wrapping_sub(old_a.rotate_left(5))
+            .wrapping_sub(old_b ^ old_c);
+
+        a = old_a;
+        b = old_b;
+        c = old_c;
+        d = old_d;
+    }
+
+    unsafe { mem::transmute::<[u16; 4], u64>([a, b, c, d]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn insert() {
+        let set = ShardedSet::new();
+        // Enough values for the shards to grow several times
+        for value in 0..2_000_000u64 {
+            assert!(set.insert(value * 0x9E37_79B9, 10));
+        }
+        for value in 0..2_000_000u64 {
+            assert!(!set.insert(value * 0x9E37_79B9, 5));
+        }
+        assert_eq!(set.len(), 2_000_000);
+        // The tables are mostly full, even right after growing
+        let table_bytes = set.memory_bytes() - ShardedSet::new().memory_bytes();
+        assert!(table_bytes < 12 * 2_000_000);
+    }
+
+    #[test]
+    fn insert_if_shallower() {
+        let set = ShardedSet::new();
+        assert!(set.insert_if_shallower(42, 10));
+        assert!(!set.insert_if_shallower(42, 10));
+        assert!(!set.insert_if_shallower(42, 11));
+        assert!(set.insert_if_shallower(42, 9));
+        assert!(!set.insert(42, 0));
+        assert!(set.insert_if_shallower(u64::MAX, u16::MAX));
+        assert!(set.insert_if_shallower(0, 0));
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn entries() {
+        let set = ShardedSet::new();
+        let mut expected = vec![(0, 0), (u64::MAX, 3), (0x0123_4567_89AB_CDEF, 7)];
+        for &(value, depth) in &expected {
+            set.insert(value, depth);
+        }
+        assert_eq!(set.depth(u64::MAX), Some(3));
+        assert_eq!(set.depth(1), None);
+
+        let mut entries = set.entries();
+        entries.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn clear() {
+        let mut set = ShardedSet::new();
+        let empty_memory = set.memory_bytes();
+        for value in 0..100_000u64 {
+            set.insert(value, 1);
+        }
+        let memory = set.memory_bytes();
+        assert!(memory > empty_memory);
+
+        set.clear();
+        assert!(set.is_empty());
+        assert_eq!(set.depth(42), None);
+        assert_eq!(set.memory_bytes(), memory);
+        assert!(set.insert(42, 2));
+        assert_eq!(set.depth(42), Some(2));
+    }
+
+    #[test]
+    fn concurrent_inserts() {
+        let set = ShardedSet::new();
+        // Every value is inserted by two threads, with different depths
+        let inserted: usize = (0..400_000u64)
+            .into_par_iter()
+            .map(|n| set.insert_if_shallower(n / 2, (n % 2) as u16) as usize)
+            .sum();
+        assert_eq!(set.len(), 200_000);
+        // The deeper insertion is only counted when it comes first
+        assert!((200_000..=400_000).contains(&inserted));
+
+        for value in 0..200_000u64 {
+            assert!(!set.insert_if_shallower(value, 0));
+        }
+    }
+}
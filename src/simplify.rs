@@ -0,0 +1,340 @@
+//! Shorten solutions by cancelling the movements of consecutive changes.
+//!
+//! A change is a compound movement, so a solution can turn a layer at the end of a change and
+//! again at the start of the next one, like around a change that only rotates the layers, or flip
+//! twice in a row. Written as twists, see [`crate::explain`], the turns between two flips are
+//! merged, the flips without a turn between them cancel out, and each turn goes the shortest way.
+
+use crate::position::Movement;
+use crate::viewer::{ViewerMovement, ViewerState};
+use anyhow::{ensure, Result};
+use itertools::Itertools;
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+/// A turn of each layer, then a flip of half of the puzzle, written like `(1,-3)/`
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Twist {
+    /// The turn of the top layer in twelfths of a turn, clockwise as seen from above, between -5
+    /// and 6
+    pub top: i8,
+    /// The turn of the bottom layer in twelfths of a turn, clockwise as seen from below, between
+    /// -5 and 6
+    pub bottom: i8,
+    /// Whether the twist ends with a flip, `/` in the usual notation. Only the last twist of a
+    /// sequence can end without one.
+    pub flip: bool,
+}
+
+/// The lengths of a sequence of twists in each metric
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize)]
+pub struct Lengths {
+    /// The flips
+    pub twist: usize,
+    /// The flips and the turns of each layer
+    pub face_turn: usize,
+    /// The twelfths of a turn of all the turns
+    pub twelfths: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Simplification {
+    pub twists: Vec<Twist>,
+    /// The lengths of the changes, like [`crate::find_solution::Solution::num_flips()`] and
+    /// [`crate::find_solution::Solution::num_face_turns()`]
+    pub before: Lengths,
+    pub after: Lengths,
+}
+
+/// The twelfths of a turn of a layer
+const LAYER_UNITS: i8 = 12;
+
+impl Twist {
+    /// The twist with the turns of the viewer, in twelfths of a turn of any size and direction
+    pub fn new(top: i8, bottom: i8, flip: bool) -> Self {
+        Twist {
+            top: shortest_turn(top),
+            bottom: shortest_turn(bottom),
+            flip,
+        }
+    }
+
+    fn is_turn_zero(self) -> bool {
+        self.top == 0 && self.bottom == 0
+    }
+
+    /// The movements of the viewer for this twist
+    pub fn viewer_movements(self) -> Vec<ViewerMovement> {
+        let mut movements = vec![];
+        if self.top != 0 {
+            movements.push(ViewerMovement::RotateTop(
+                self.top.rem_euclid(LAYER_UNITS) as u8
+            ));
+        }
+        if self.bottom != 0 {
+            movements.push(ViewerMovement::RotateBottom(
+                self.bottom.rem_euclid(LAYER_UNITS) as u8,
+            ));
+        }
+        if self.flip {
+            movements.push(ViewerMovement::Flip);
+        }
+        movements
+    }
+}
+
+impl Lengths {
+    /// The lengths of the movements of the viewer, as they are performed, which always turn the
+    /// layers in the same direction
+    fn new(movements: &[ViewerMovement]) -> Self {
+        let mut lengths = Lengths::default();
+        for &movement in movements {
+            match movement {
+                ViewerMovement::RotateTop(units) | ViewerMovement::RotateBottom(units) => {
+                    lengths.face_turn += 1;
+                    lengths.twelfths += units as usize;
+                }
+                ViewerMovement::Flip => {
+                    lengths.twist += 1;
+                    lengths.face_turn += 1;
+                }
+            }
+        }
+        lengths
+    }
+
+    /// The lengths of the twists, which turn the layers the shortest way
+    fn of_twists(twists: &[Twist]) -> Self {
+        let mut lengths = Lengths::default();
+        for twist in twists {
+            for turn in [twist.top, twist.bottom] {
+                if turn != 0 {
+                    lengths.face_turn += 1;
+                    lengths.twelfths += turn.unsigned_abs() as usize;
+                }
+            }
+            if twist.flip {
+                lengths.twist += 1;
+                lengths.face_turn += 1;
+            }
+        }
+        lengths
+    }
+}
+
+/// Merge the turns and cancel the flips of the movements of a solution, see
+/// [`crate::find_solution::Solution::movements()`], checking that the twists lead to the same
+/// position
+pub fn simplify(movements: &[Movement]) -> Result<Simplification> {
+    ensure!(
+        !movements.is_empty(),
+        "a solution starts with the movement of its initial position"
+    );
+    let initial = ViewerState::from_position(movements[0].position());
+    let mut viewer_movements = vec![];
+    let mut state = initial.clone();
+    for movement in &movements[1..] {
+        let mut change_movements = vec![];
+        state.push_change(movement.change(), &mut change_movements)?;
+        // The flips of a rotation are not counted
+        if movement.change().is_rotation() {
+            change_movements.retain(|&movement| movement != ViewerMovement::Flip);
+        }
+        viewer_movements.extend(change_movements);
+    }
+
+    let mut twists: Vec<Twist> = vec![];
+    for &movement in &viewer_movements {
+        match movement {
+            ViewerMovement::RotateTop(units) | ViewerMovement::RotateBottom(units) => {
+                if !matches!(twists.last(), Some(twist) if !twist.flip) {
+                    twists.push(Twist::default());
+                }
+                let last = twists.last_mut().expect("a twist was pushed");
+                *last = if let ViewerMovement::RotateTop(_) = movement {
+                    Twist::new(last.top + units as i8, last.bottom, false)
+                } else {
+                    Twist::new(last.top, last.bottom + units as i8, false)
+                };
+            }
+            ViewerMovement::Flip => {
+                // Turns that add up to nothing don't separate flips
+                if twists
+                    .last()
+                    .filter(|twist| !twist.flip && twist.is_turn_zero())
+                    .is_some()
+                {
+                    twists.pop();
+                }
+                match twists.last_mut() {
+                    // The flips cancel out, and the next turns are merged with the previous ones
+                    Some(last) if last.flip => last.flip = false,
+                    Some(last) => last.flip = true,
+                    None => twists.push(Twist::new(0, 0, true)),
+                }
+            }
+        }
+    }
+    if twists
+        .last()
+        .filter(|twist| !twist.flip && twist.is_turn_zero())
+        .is_some()
+    {
+        twists.pop();
+    }
+
+    let mut simplified_state = initial;
+    for movement in twists.iter().flat_map(|twist| twist.viewer_movements()) {
+        simplified_state.apply(movement)?;
+    }
+    ensure!(
+        simplified_state == state,
+        "the simplified twists lead to {} instead of {}",
+        simplified_state,
+        state
+    );
+
+    Ok(Simplification {
+        before: Lengths::new(&viewer_movements),
+        after: Lengths::of_twists(&twists),
+        twists,
+    })
+}
+
+/// The same turn between -5 and 6 twelfths
+fn shortest_turn(units: i8) -> i8 {
+    let units = units.rem_euclid(LAYER_UNITS);
+    if units > LAYER_UNITS / 2 {
+        units - LAYER_UNITS
+    } else {
+        units
+    }
+}
+
+impl fmt::Display for Twist {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({},{})", self.top, self.bottom)?;
+        if self.flip {
+            f.write_str("/")?;
+        }
+        Ok(())
+    }
+}
+
+/// Twists are serialized like their `Display`
+impl Serialize for Twist {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl fmt::Display for Simplification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.twists.iter().join(" "))?;
+        write!(
+            f,
+            "{} -> {} twists, {} -> {} face turns, {} -> {} twelfths of turns",
+            self.before.twist,
+            self.after.twist,
+            self.before.face_turn,
+            self.after.face_turn,
+            self.before.twelfths,
+            self.after.twelfths
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Change;
+    use crate::Position;
+
+    /// The movements from the solved position through the changes, packed like
+    /// [`Change::as_bytes()`]
+    fn movements(changes: &[u16]) -> Vec<Movement> {
+        let mut movements = vec![Movement::initial_movement(Position::solved())];
+        for &bytes in changes {
+            let change = Change::from_bytes(bytes);
+            let position = movements[movements.len() - 1]
+                .position()
+                .apply(change)
+                .unwrap();
+            movements.push(Movement::new(change, position));
+        }
+        movements
+    }
+
+    #[test]
+    fn merge_turns() {
+        // The rotation of the top layer is merged with the first turn of the next change, and
+        // both go the shortest way
+        let simplification = simplify(&movements(&[0x2000, 0x2020])).unwrap();
+        assert_eq!(
+            simplification.twists,
+            vec![Twist::new(6, 0, true), Twist::new(-3, 0, true)]
+        );
+        assert_eq!(
+            (simplification.before.twist, simplification.after.twist),
+            (2, 2)
+        );
+        assert_eq!(
+            (
+                simplification.before.face_turn,
+                simplification.after.face_turn
+            ),
+            (5, 4)
+        );
+        assert_eq!(
+            (
+                simplification.before.twelfths,
+                simplification.after.twelfths
+            ),
+            (27, 9)
+        );
+        assert_eq!(
+            simplification.to_string(),
+            "(6,0)/ (-3,0)/\n2 -> 2 twists, 5 -> 4 face turns, 27 -> 9 twelfths of turns"
+        );
+
+        // The last flip of the first change and the first one of the second change cancel out
+        let simplification = simplify(&movements(&[0x2002, 0x0020])).unwrap();
+        assert_eq!(
+            simplification.twists,
+            vec![Twist::new(-3, 0, true), Twist::new(-3, -3, true)]
+        );
+        assert_eq!(
+            (simplification.before.twist, simplification.after.twist),
+            (4, 2)
+        );
+    }
+
+    #[test]
+    fn cancel_changes() {
+        // A rotation followed by the opposite one does nothing
+        let simplification = simplify(&movements(&[0x2000, 0x6000])).unwrap();
+        assert!(simplification.twists.is_empty());
+        assert_eq!(simplification.before.face_turn, 2);
+        assert_eq!(simplification.after, Lengths::default());
+        assert!(simplify(&[]).is_err());
+    }
+
+    #[test]
+    fn notation() {
+        assert_eq!(shortest_turn(0), 0);
+        assert_eq!(shortest_turn(6), 6);
+        assert_eq!(shortest_turn(7), -5);
+        assert_eq!(shortest_turn(-7), 5);
+        assert_eq!(shortest_turn(23), -1);
+        assert_eq!(Twist::new(13, 10, true).to_string(), "(1,-2)/");
+        assert_eq!(Twist::new(0, 3, false).to_string(), "(0,3)");
+        assert_eq!(
+            Twist::new(-1, 6, true).viewer_movements(),
+            vec![
+                ViewerMovement::RotateTop(11),
+                ViewerMovement::RotateBottom(6),
+                ViewerMovement::Flip
+            ]
+        );
+    }
+}
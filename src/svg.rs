@@ -0,0 +1,318 @@
+//! Top-down diagrams of the puzzle in SVG, to show positions without the viewer in `web3d/`.
+//!
+//! The top layer is drawn as seen from above, and the bottom layer as seen from below after
+//! turning the puzzle over around the slice, so that the pieces that a flip exchanges are on the
+//! same side of the slice in both drawings. The pieces of each layer go counterclockwise from the
+//! slice, in the order of [`Position::pieces()`]. The front of the middle layer is drawn below,
+//! split in the middle when it's solved and unevenly when it's turned.
+
+use crate::find_solution::Solution;
+use crate::piece::Piece;
+use crate::viewer::ViewerState;
+use crate::Position;
+use anyhow::{bail, Context, Error, Result};
+use std::f64::consts::PI;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// The CSS colours of the faces, by the letters of the names of the pieces
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ColourScheme {
+    pub white: String,
+    pub yellow: String,
+    pub red: String,
+    pub blue: String,
+    pub orange: String,
+    pub green: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SvgRenderer {
+    scheme: ColourScheme,
+    /// The width of the drawing of a layer, in pixels
+    size: f64,
+}
+
+/// The angle of a unit of a layer, where a small piece has 1 unit and a big piece has 2
+const UNIT_ANGLE: f64 = PI / 6.0;
+
+/// The angle of the slice, from the horizontal, when the layers are squares
+const SLICE_ANGLE: f64 = PI / 12.0;
+
+/// How far the stickers of the sides go, relative to the side of the layer
+const STICKER_SCALE: f64 = 1.2;
+
+impl ColourScheme {
+    /// The colour of a letter of the name of a piece
+    fn colour(&self, letter: char) -> &str {
+        match letter {
+            'W' => &self.white,
+            'Y' => &self.yellow,
+            'R' => &self.red,
+            'B' => &self.blue,
+            'O' => &self.orange,
+            _ => &self.green,
+        }
+    }
+}
+
+/// The colours of the viewer
+impl Default for ColourScheme {
+    fn default() -> Self {
+        ColourScheme {
+            white: "white".to_string(),
+            yellow: "yellow".to_string(),
+            red: "red".to_string(),
+            blue: "blue".to_string(),
+            orange: "darkorange".to_string(),
+            green: "green".to_string(),
+        }
+    }
+}
+
+impl FromStr for ColourScheme {
+    type Err = Error;
+
+    /// Parse the colours that differ from the default scheme, like `W=#eee,O=orange`
+    fn from_str(s: &str) -> Result<Self> {
+        let mut scheme = ColourScheme::default();
+        for entry in s.split(',').filter(|entry| !entry.is_empty()) {
+            let (letter, colour) = entry
+                .split_once('=')
+                .with_context(|| format!("expected a letter and a colour in {}", entry))?;
+            // The colours end up in attributes, so they can't close them
+            if colour.is_empty() || colour.contains(['"', '<', '>', '&']) {
+                bail!("invalid colour: {}", colour);
+            }
+
+            let field = match letter {
+                "W" => &mut scheme.white,
+                "Y" => &mut scheme.yellow,
+                "R" => &mut scheme.red,
+                "B" => &mut scheme.blue,
+                "O" => &mut scheme.orange,
+                "G" => &mut scheme.green,
+                _ => bail!(
+                    "invalid face: {}, expected one of W, Y, R, B, O and G",
+                    letter
+                ),
+            };
+            *field = colour.to_string();
+        }
+        Ok(scheme)
+    }
+}
+
+impl SvgRenderer {
+    pub fn new(scheme: ColourScheme) -> Self {
+        SvgRenderer {
+            scheme,
+            size: 200.0,
+        }
+    }
+
+    /// The width of the drawing of a layer, in pixels. The whole diagram is a bit more than twice
+    /// as wide.
+    pub fn size(mut self, size: f64) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn position(&self, position: Position) -> String {
+        self.viewer_state(&ViewerState::from_position(position))
+    }
+
+    /// Draw a state of the viewer, whose layers may not be sliceable and whose middle layer may be
+    /// turned
+    pub fn viewer_state(&self, state: &ViewerState) -> String {
+        let size = self.size;
+        let margin = size / 10.0;
+        let middle_height = size / 8.0;
+        let width = 2.0 * size + 3.0 * margin;
+        let height = size + middle_height + 3.0 * margin;
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.0}" height="{:.0}" viewBox="0 0 {:.0} {:.0}">"#,
+            width, height, width, height
+        );
+        let _ = writeln!(
+            svg,
+            r#"<g stroke="black" stroke-width="{:.1}" stroke-linejoin="round">"#,
+            size / 100.0
+        );
+        self.draw_layer(
+            &mut svg,
+            state.top(),
+            margin + size / 2.0,
+            margin + size / 2.0,
+        );
+        self.draw_layer(
+            &mut svg,
+            state.bottom(),
+            2.0 * margin + 1.5 * size,
+            margin + size / 2.0,
+        );
+        self.draw_middle(
+            &mut svg,
+            state.is_middle_solved(),
+            margin,
+            2.0 * margin + size,
+            width - 2.0 * margin,
+            middle_height,
+        );
+        svg.push_str("</g>\n</svg>\n");
+        svg
+    }
+
+    /// A diagram of each position of the solution, starting with the initial one
+    pub fn solution(&self, solution: &Solution) -> Vec<String> {
+        solution
+            .movements()
+            .iter()
+            .map(|movement| self.position(movement.position()))
+            .collect()
+    }
+
+    /// Draw the pieces of a layer around `(x, y)`. The layer is a square of side 2 before it's
+    /// scaled, and each piece is a triangle or a kite from its center.
+    fn draw_layer(&self, svg: &mut String, pieces: &[Piece], x: f64, y: f64) {
+        let scale = self.size / 2.0 / (2f64.sqrt() * STICKER_SCALE);
+        let point = |angle: f64, radius: f64| {
+            // The y axis of SVG goes down, so counterclockwise angles are negated
+            (
+                x + scale * radius * angle.cos(),
+                y - scale * radius * angle.sin(),
+            )
+        };
+        // The points where the unit boundaries meet the square
+        let boundary = |angle: f64| point(angle, 1.0 / (UNIT_ANGLE / 2.0).cos());
+
+        let mut angle = SLICE_ANGLE;
+        for piece in pieces {
+            let name = piece.to_string();
+            let mut letters = name.chars();
+            let face = self
+                .scheme
+                .colour(letters.next().expect("a piece has a face"));
+
+            let start = boundary(angle);
+            let end = boundary(angle + piece.size() as f64 * UNIT_ANGLE);
+            let outline = if piece.size() == 2 {
+                vec![start, point(angle + UNIT_ANGLE, 2f64.sqrt()), end]
+            } else {
+                vec![start, end]
+            };
+
+            let mut face_points = vec![(x, y)];
+            face_points.extend(&outline);
+            polygon(svg, face, &face_points);
+
+            // Each side of the outline has a sticker, extended outwards from the center
+            for (segment, letter) in outline.windows(2).zip(letters) {
+                let outer = |(px, py): (f64, f64)| {
+                    (x + (px - x) * STICKER_SCALE, y + (py - y) * STICKER_SCALE)
+                };
+                polygon(
+                    svg,
+                    self.scheme.colour(letter),
+                    &[segment[0], segment[1], outer(segment[1]), outer(segment[0])],
+                );
+            }
+
+            angle += piece.size() as f64 * UNIT_ANGLE;
+        }
+
+        let (slice_start, slice_end) = (point(SLICE_ANGLE, 1.8), point(SLICE_ANGLE + PI, 1.8));
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke-dasharray="4"/>"#,
+            slice_start.0, slice_start.1, slice_end.0, slice_end.1
+        );
+    }
+
+    fn draw_middle(&self, svg: &mut String, solved: bool, x: f64, y: f64, width: f64, height: f64) {
+        // When the middle layer is turned, its front shows a third of a piece and two thirds of
+        // the other one, like the back of the turned one
+        let (split, right) = if solved {
+            (width / 2.0, &self.scheme.red)
+        } else {
+            (width / 3.0, &self.scheme.orange)
+        };
+        for (start, end, colour) in [
+            (x, x + split, &self.scheme.red),
+            (x + split, x + width, right),
+        ] {
+            polygon(
+                svg,
+                colour,
+                &[(start, y), (end, y), (end, y + height), (start, y + height)],
+            );
+        }
+    }
+}
+
+impl Default for SvgRenderer {
+    fn default() -> Self {
+        SvgRenderer::new(ColourScheme::default())
+    }
+}
+
+fn polygon(svg: &mut String, fill: &str, points: &[(f64, f64)]) {
+    svg.push_str(r#"<polygon points=""#);
+    for (n, (x, y)) in points.iter().enumerate() {
+        if n > 0 {
+            svg.push(' ');
+        }
+        let _ = write!(svg, "{:.1},{:.1}", x, y);
+    }
+    let _ = writeln!(svg, r#"" fill="{}"/>"#, fill);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::viewer::ViewerMovement;
+
+    #[test]
+    fn solved() {
+        let svg = SvgRenderer::default().position(Position::solved());
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>\n"));
+
+        // Each piece has a face, the small ones a sticker on their side and the big ones two,
+        // and the middle layer has two parts
+        assert_eq!(svg.matches("<polygon").count(), 16 + 8 + 2 * 8 + 2);
+        assert_eq!(svg.matches(r#"fill="white""#).count(), 8);
+        assert_eq!(svg.matches(r#"fill="yellow""#).count(), 8);
+        assert_eq!(svg.matches(r#"fill="red""#).count(), 6 + 2);
+        assert_eq!(svg.matches(r#"fill="darkorange""#).count(), 6);
+        assert_eq!(svg.matches("<line").count(), 2);
+    }
+
+    #[test]
+    fn turned_middle() {
+        let mut state = ViewerState::solved();
+        state.apply(ViewerMovement::RotateTop(1)).unwrap_err();
+        state.apply(ViewerMovement::RotateTop(3)).unwrap();
+        state.apply(ViewerMovement::Flip).unwrap();
+        assert!(!state.is_middle_solved());
+
+        let scheme: ColourScheme = "O=#f80,W=#eee".parse().unwrap();
+        let svg = SvgRenderer::new(scheme).size(100.0).viewer_state(&state);
+        assert!(svg.contains(r#"width="230""#));
+        assert_eq!(svg.matches(r##"fill="#eee""##).count(), 8);
+        assert_eq!(svg.matches(r##"fill="#f80""##).count(), 6 + 1);
+    }
+
+    #[test]
+    fn colour_schemes() {
+        assert_eq!("".parse::<ColourScheme>().unwrap(), ColourScheme::default());
+        let scheme: ColourScheme = "G=lime".parse().unwrap();
+        assert_eq!(scheme.green, "lime");
+        assert!("X=red".parse::<ColourScheme>().is_err());
+        assert!("W".parse::<ColourScheme>().is_err());
+        assert!(r#"W=red"/><script"#.parse::<ColourScheme>().is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Symmetries of the puzzle, to consider equivalent positions only once.
+//!
+//! A symmetry is a transformation of the whole puzzle that maps the solved position to itself and
+//! commutes with every change: the neighbours of a transformed position are the transformed
+//! neighbours. So a position and its transformation are at the same distance from the solved
+//! position, and only one of them needs to be explored.
+//!
+//! Only two transformations have both properties, together with their composition:
+//! - turning the whole puzzle by 180° around the vertical axis, which exchanges the half-layers of
+//!   each layer and the opposite side colors
+//! - turning the whole puzzle upside down around the axis of the slice, which exchanges the
+//!   layers, reverses the order of the pieces and relabels the colors to match
+//!
+//! The other relabellings of the colors, turns by 90° and mirrors don't keep the solved position,
+//! or the slice, in place.
+
+use crate::move_tables;
+use crate::position::BITS_PER_PIECE;
+use crate::Position;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Symmetry {
+    Identity,
+    /// Turn the whole puzzle by 180° around the vertical axis
+    HalfTurn,
+    /// Turn the whole puzzle upside down around the axis of the slice
+    UpsideDown,
+    /// Both [`Symmetry::HalfTurn`] and [`Symmetry::UpsideDown`]
+    HalfTurnUpsideDown,
+}
+
+/// The symmetries used to identify equivalent positions
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum SymmetryGroup {
+    /// Every position is only equivalent to itself
+    Trivial,
+    HalfTurn,
+    UpsideDown,
+    /// All the [`Symmetry`], which divides the number of positions by about 4
+    Full,
+}
+
+/// The lowest bit of each group of 4 bits
+const LOW_BITS: u64 = 0x1111_1111_1111_1111;
+
+/// Each piece is `seq << 1 | yellow`, so adding 4 to every sequence number is flipping this bit
+const HALF_SEQUENCE_BITS: u64 = LOW_BITS << 3;
+
+impl Symmetry {
+    /// Transform the position. Each symmetry is its own inverse.
+    pub fn apply(self, position: Position) -> Position {
+        let bits = position.as_bytes();
+        Position::from_bytes(match self {
+            Symmetry::Identity => bits,
+            Symmetry::HalfTurn => half_turn(bits),
+            Symmetry::UpsideDown => upside_down(bits),
+            Symmetry::HalfTurnUpsideDown => half_turn(upside_down(bits)),
+        })
+    }
+}
+
+impl SymmetryGroup {
+    pub fn symmetries(self) -> &'static [Symmetry] {
+        use Symmetry::*;
+
+        match self {
+            SymmetryGroup::Trivial => &[Identity],
+            SymmetryGroup::HalfTurn => &[Identity, HalfTurn],
+            SymmetryGroup::UpsideDown => &[Identity, UpsideDown],
+            SymmetryGroup::Full => &[Identity, HalfTurn, UpsideDown, HalfTurnUpsideDown],
+        }
+    }
+
+    /// The smallest position among the transformations of the position, which is the same for
+    /// all the equivalent positions
+    pub fn canonical(self, position: Position) -> Position {
+        self.symmetries()
+            .iter()
+            .map(|symmetry| symmetry.apply(position))
+            .min()
+            .expect("the identity is always part of the group")
+    }
+}
+
+/// Exchange the half-layers of each layer, and the colors of opposite sides
+fn half_turn(bits: u64) -> u64 {
+    let [top_first, top_second, bottom_first, bottom_second] =
+        move_tables::split(bits).expect("the position cannot be sliced");
+    let [top_first, top_second, bottom_first, bottom_second] =
+        [top_first, top_second, bottom_first, bottom_second]
+            .map(|num_pieces| BITS_PER_PIECE * num_pieces as u32);
+
+    let mask = |width: u32| (1 << width) - 1;
+    let bottom_second_bits = bits & mask(bottom_second);
+    let bottom_first_bits = (bits >> bottom_second) & mask(bottom_first);
+    let top_second_bits = (bits >> (bottom_second + bottom_first)) & mask(top_second);
+    let top_first_bits = bits >> (bottom_second + bottom_first + top_second);
+
+    let top = (top_second_bits << top_first) | top_first_bits;
+    let bottom = (bottom_second_bits << bottom_first) | bottom_first_bits;
+    ((top << (bottom_first + bottom_second)) | bottom) ^ HALF_SEQUENCE_BITS
+}
+
+/// Reverse the order of the pieces, which also exchanges the layers since both have 12 units, and
+/// mirror the colors: the sequence numbers are negated and white and yellow are exchanged
+fn upside_down(bits: u64) -> u64 {
+    let reversed = bits.swap_bytes();
+    let reversed =
+        ((reversed >> 4) & 0x0F0F_0F0F_0F0F_0F0F) | ((reversed & 0x0F0F_0F0F_0F0F_0F0F) << 4);
+
+    // `16 - 2 * seq` is `(14 - 2 * seq) + 2`, and the subtraction doesn't borrow. The highest bit
+    // is added back without carry, so that no group overflows into the next one
+    let sequences = reversed & !LOW_BITS;
+    let complement = sequences ^ !LOW_BITS;
+    let negated =
+        ((complement & !HALF_SEQUENCE_BITS) + (LOW_BITS << 1)) ^ (complement & HALF_SEQUENCE_BITS);
+    negated | ((reversed & LOW_BITS) ^ LOW_BITS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::NeighboursStack;
+    use std::collections::BTreeSet;
+
+    /// The positions along a pseudo-random walk
+    fn sample_positions() -> Vec<Position> {
+        let mut stack = NeighboursStack::new();
+        let mut position = Position::solved();
+        let mut seed = 0x2545_F491_4F6C_DD1D_u64;
+        (0..30)
+            .map(|_| {
+                position.neighbours(&mut stack);
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                position = stack.neighbours()[seed as usize % stack.neighbours().len()].position();
+                position
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keep_solved() {
+        for &symmetry in SymmetryGroup::Full.symmetries() {
+            assert_eq!(symmetry.apply(Position::solved()), Position::solved());
+        }
+    }
+
+    #[test]
+    fn commute_with_changes() {
+        let mut stack = NeighboursStack::new();
+        for position in sample_positions() {
+            for &symmetry in SymmetryGroup::Full.symmetries() {
+                let transformed = symmetry.apply(position);
+                assert_eq!(symmetry.apply(transformed), position);
+
+                position.neighbours(&mut stack);
+                let expected: BTreeSet<_> = stack
+                    .neighbours()
+                    .iter()
+                    .map(|movement| symmetry.apply(movement.position()))
+                    .collect();
+                transformed.neighbours(&mut stack);
+                let actual: BTreeSet<_> = stack
+                    .neighbours()
+                    .iter()
+                    .map(|movement| movement.position())
+                    .collect();
+                assert_eq!(actual, expected, "{:?} of {}", symmetry, position);
+            }
+        }
+    }
+
+    #[test]
+    fn canonical() {
+        for position in sample_positions() {
+            let canonical = SymmetryGroup::Full.canonical(position);
+            for &symmetry in SymmetryGroup::Full.symmetries() {
+                assert_eq!(
+                    SymmetryGroup::Full.canonical(symmetry.apply(position)),
+                    canonical
+                );
+            }
+            assert_eq!(SymmetryGroup::Trivial.canonical(position), position);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Diagrams of positions for the terminal, easier to read than the names of the pieces written by
+//! [`Position`]'s `Display`.
+//!
+//! Each layer is drawn as a strip of its 12 units, in the order of [`Position::pieces()`], with a
+//! `┃` where it's sliced. The faces of the pieces on the top or bottom are drawn as wedges,
+//! `◥████◤`, for the big pieces and as slots, `▕█▏`, for the small ones, and the stickers on their
+//! sides are drawn next to them, towards the middle layer. With ANSI colours the blocks have the
+//! colours of the stickers, and without them they are the letters of the stickers.
+
+use crate::find_solution::Solution;
+use crate::piece::Piece;
+use crate::Position;
+use itertools::Itertools;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TerminalRenderer {
+    colours: bool,
+}
+
+/// The units of a layer, where a small piece has 1 unit and a big piece has 2
+const LAYER_UNITS: u8 = 12;
+
+/// The width of a unit, in characters
+const UNIT_WIDTH: usize = 3;
+
+/// The width of the names of the layers before the strips
+const LABEL_WIDTH: usize = 8;
+
+impl TerminalRenderer {
+    pub fn new() -> Self {
+        TerminalRenderer { colours: true }
+    }
+
+    /// Whether to use ANSI colours, or letters for the terminals and files without them
+    pub fn colours(mut self, colours: bool) -> Self {
+        self.colours = colours;
+        self
+    }
+
+    /// The diagram of both layers, on 4 lines: the faces and sides of the top layer, then the sides
+    /// and faces of the bottom layer
+    pub fn position(&self, position: Position) -> String {
+        let pieces = position.pieces();
+        let mut units = 0;
+        let num_top_pieces = pieces
+            .iter()
+            .take_while(|piece| {
+                units += piece.size();
+                units <= LAYER_UNITS
+            })
+            .count();
+        let (top, bottom) = pieces.split_at(num_top_pieces);
+
+        let mut diagram = String::new();
+        for (label, row) in [
+            ("Top", self.faces(top)),
+            ("", self.sides(top)),
+            ("", self.sides(bottom)),
+            ("Bottom", self.faces(bottom)),
+        ] {
+            let _ = writeln!(diagram, "{:<2$}{}", label, row, LABEL_WIDTH);
+        }
+        diagram
+    }
+
+    /// The diagram of the initial position of the solution, then of the position after each of
+    /// its changes
+    pub fn solution(&self, solution: &Solution) -> String {
+        let movements = solution.movements();
+        let mut diagrams = format!("Start\n{}", self.position(movements[0].position()));
+        for (n, movement) in movements[1..].iter().enumerate() {
+            let _ = write!(
+                diagrams,
+                "\n{}. {}\n{}",
+                n + 1,
+                movement.change(),
+                self.position(movement.position())
+            );
+        }
+        diagrams
+    }
+
+    fn faces(&self, pieces: &[Piece]) -> String {
+        self.row(pieces, |piece, letters| {
+            let face = letters[0];
+            if piece.size() == 2 {
+                vec![
+                    (face, "◥"),
+                    (face, "█"),
+                    (face, "█"),
+                    (face, "█"),
+                    (face, "█"),
+                    (face, "◤"),
+                ]
+            } else {
+                vec![(face, "▕"), (face, "█"), (face, "▏")]
+            }
+        })
+    }
+
+    fn sides(&self, pieces: &[Piece]) -> String {
+        self.row(pieces, |_, letters| {
+            letters[1..]
+                .iter()
+                .flat_map(|&side| [(side, "█"); UNIT_WIDTH])
+                .collect()
+        })
+    }
+
+    /// A row of a layer, where `cells` gives the character of each column of a piece, from the
+    /// letters of its name, with the letter of its colour. Without colours, the blocks are
+    /// replaced by the letters.
+    fn row(
+        &self,
+        pieces: &[Piece],
+        cells: impl Fn(Piece, &[char]) -> Vec<(char, &'static str)>,
+    ) -> String {
+        let mut row = String::new();
+        let mut units = 0;
+        let mut sliced = false;
+        for &piece in pieces {
+            let letters = piece.to_string().chars().collect_vec();
+            for (letter, character) in cells(piece, &letters) {
+                match (self.colours, character) {
+                    (true, _) => {
+                        let _ = write!(row, "\x1b[38;5;{}m{}\x1b[0m", colour(letter), character);
+                    }
+                    (false, "█") => row.push(letter),
+                    (false, _) => row.push_str(character),
+                }
+            }
+
+            units += piece.size();
+            if units == LAYER_UNITS / 2 {
+                row.push('┃');
+                sliced = true;
+            }
+        }
+        // Keep the layers aligned when one of them can't be sliced
+        if !sliced {
+            row.push(' ');
+        }
+        row
+    }
+}
+
+impl Default for TerminalRenderer {
+    fn default() -> Self {
+        TerminalRenderer::new()
+    }
+}
+
+/// The colour of a letter of the name of a piece, in the 256 colours of ANSI terminals
+fn colour(letter: char) -> u8 {
+    match letter {
+        'W' => 231,
+        'Y' => 226,
+        'R' => 196,
+        'B' => 27,
+        'O' => 208,
+        _ => 34,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_solution::solve_small;
+    use crate::scramble::scramble;
+
+    #[test]
+    fn solved() {
+        let diagram = TerminalRenderer::new()
+            .colours(false)
+            .position(Position::solved());
+        assert_eq!(
+            diagram,
+            "Top     ◥WWWW◤▕W▏◥WWWW◤▕W▏┃◥WWWW◤▕W▏◥WWWW◤▕W▏\n        \
+             RRRBBBBBBBBBOOOOOO┃OOOGGGGGGGGGRRRRRR\n        \
+             OOOOOOBBBBBBBBBRRR┃RRRRRRGGGGGGGGGOOO\n\
+             Bottom  ▕Y▏◥YYYY◤▕Y▏◥YYYY◤┃▕Y▏◥YYYY◤▕Y▏◥YYYY◤\n"
+        );
+
+        // Each column of the strips has its colour, and the letters are gone
+        let diagram = TerminalRenderer::new().position(Position::solved());
+        assert_eq!(diagram.matches("\x1b[38;5;").count(), 4 * 12 * UNIT_WIDTH);
+        assert_eq!(diagram.matches("\x1b[38;5;208m").count(), 6 * UNIT_WIDTH);
+        assert!(!diagram.contains('W'));
+    }
+
+    #[test]
+    fn solution() {
+        let movements = scramble(3, 1);
+        let position = movements[movements.len() - 1].position();
+        let solution = solve_small(position);
+
+        let renderer = TerminalRenderer::new().colours(false);
+        let diagrams = renderer.solution(&solution);
+        assert!(diagrams.starts_with(&format!("Start\n{}", renderer.position(position))));
+        assert!(diagrams.ends_with(&renderer.position(Position::solved())));
+        assert_eq!(
+            diagrams.lines().count(),
+            5 * solution.movements().len() + solution.movements().len() - 1
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! A trace of the expansions of a search, to study how it moves through the positions without
+//! running it again, see [`crate::find_solution::SolverBuilder::trace_file()`].
+//!
+//! The file starts with [`MAGIC`], followed by a record of [`RECORD_BYTES`] for each expansion, in
+//! little endian: the position, the packed score, depth and index of the node like the keys of the
+//! queue, the index of its parent, and how many neighbours were generated and enqueued. The
+//! records of different threads are interleaved in blocks.
+
+use crate::Position;
+use anyhow::{anyhow, ensure, Context, Result};
+use itertools::Itertools;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+/// The start of a trace file, with the version of the format
+pub const MAGIC: &[u8; 8] = b"SQ1TRC01";
+
+pub const RECORD_BYTES: usize = 28;
+
+const INDEX_BITS: u32 = 40;
+
+/// The index of the parent of the initial node
+const NO_PARENT: u64 = u64::MAX;
+
+/// The expansion of a node
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TraceRecord {
+    pub position: Position,
+    pub score: u8,
+    pub depth: u16,
+    pub index: u64,
+    /// `None` for the initial node
+    pub parent: Option<u64>,
+    /// The neighbours that were generated, including the ones already seen
+    pub generated: u16,
+    /// The neighbours that were new, or reached by a shorter path, and were enqueued. When the seen
+    /// positions are checked in batches, like on the disk, it's the neighbours that were buffered
+    /// to be checked.
+    pub enqueued: u16,
+}
+
+/// Writes the records of the threads of a search into a file
+#[derive(Debug)]
+pub struct TraceWriter {
+    file: Mutex<BufWriter<File>>,
+}
+
+/// Reads the records of a trace file, failing on a truncated record
+#[derive(Debug)]
+pub struct TraceReader<R> {
+    reader: R,
+}
+
+/// The aggregate of a trace, see [`TraceSummary::new()`]
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceSummary {
+    pub num_expansions: usize,
+    /// The distinct positions that were expanded
+    pub num_positions: usize,
+    pub max_depth: u16,
+    pub generated: u64,
+    pub enqueued: u64,
+    pub depth_bucket: u16,
+    /// For each bucket of depths, by its first depth, the number of expansions with each score
+    pub score_by_depth: BTreeMap<u16, BTreeMap<u8, usize>>,
+    /// For each number of expansions, how many positions were expanded that many times. A position
+    /// is expanded again when it's reached by a shorter path, or through a symmetric position when
+    /// symmetries are ignored.
+    pub expansions_per_position: BTreeMap<usize, usize>,
+}
+
+impl TraceRecord {
+    fn to_bytes(self) -> [u8; RECORD_BYTES] {
+        let key = (self.score as u64) << (u64::BITS - 8)
+            | (self.depth as u64) << INDEX_BITS
+            | self.index & ((1 << INDEX_BITS) - 1);
+        let counts = (self.generated as u32) << 16 | self.enqueued as u32;
+
+        let mut bytes = [0; RECORD_BYTES];
+        bytes[..8].copy_from_slice(&self.position.as_bytes().to_le_bytes());
+        bytes[8..16].copy_from_slice(&key.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.parent.unwrap_or(NO_PARENT).to_le_bytes());
+        bytes[24..].copy_from_slice(&counts.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; RECORD_BYTES]) -> Self {
+        let word =
+            |start: usize| u64::from_le_bytes(bytes[start..start + 8].try_into().expect("8 bytes"));
+        let key = word(8);
+        let parent = word(16);
+        let counts = u32::from_le_bytes(bytes[24..].try_into().expect("4 bytes"));
+
+        TraceRecord {
+            position: Position::from_bytes(word(0)),
+            score: (key >> (u64::BITS - 8)) as u8,
+            depth: (key >> INDEX_BITS) as u16,
+            index: key & ((1 << INDEX_BITS) - 1),
+            parent: if parent == NO_PARENT {
+                None
+            } else {
+                Some(parent)
+            },
+            generated: (counts >> 16) as u16,
+            enqueued: counts as u16,
+        }
+    }
+}
+
+impl TraceWriter {
+    /// Create the file, replacing an existing one
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create the trace {}", path.display()))?;
+        let mut file = BufWriter::new(file);
+        file.write_all(MAGIC)?;
+        Ok(TraceWriter {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append the records. They are written together, so that the records of a thread are not
+    /// split by the ones of another thread.
+    pub fn write(&self, records: &[TraceRecord]) -> Result<()> {
+        let mut file = self.file.lock();
+        for record in records {
+            file.write_all(&record.to_bytes())
+                .context("failed to write the trace")?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.file
+            .lock()
+            .flush()
+            .context("failed to write the trace")
+    }
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Check that the reader starts with [`MAGIC`]
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 8];
+        reader
+            .read_exact(&mut magic)
+            .context("failed to read the header of the trace")?;
+        ensure!(&magic == MAGIC, "not a trace file, or of another version");
+        Ok(TraceReader { reader })
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = [0; RECORD_BYTES];
+        let mut read = 0;
+        while read < RECORD_BYTES {
+            match self.reader.read(&mut bytes[read..]) {
+                Ok(0) if read == 0 => return None,
+                Ok(0) => return Some(Err(anyhow!("the last record is truncated"))),
+                Ok(n) => read += n,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Some(Err(error.into())),
+            }
+        }
+        Some(Ok(TraceRecord::from_bytes(&bytes)))
+    }
+}
+
+impl TraceSummary {
+    /// Aggregate the records, grouping the depths by buckets of `depth_bucket`
+    pub fn new(
+        records: impl Iterator<Item = Result<TraceRecord>>,
+        depth_bucket: u16,
+    ) -> Result<Self> {
+        ensure!(depth_bucket > 0, "the depth bucket must be positive");
+
+        let mut summary = TraceSummary {
+            num_expansions: 0,
+            num_positions: 0,
+            max_depth: 0,
+            generated: 0,
+            enqueued: 0,
+            depth_bucket,
+            score_by_depth: BTreeMap::new(),
+            expansions_per_position: BTreeMap::new(),
+        };
+        let mut expansions = HashMap::new();
+
+        for record in records {
+            let record = record?;
+            summary.num_expansions += 1;
+            summary.max_depth = summary.max_depth.max(record.depth);
+            summary.generated += record.generated as u64;
+            summary.enqueued += record.enqueued as u64;
+            *summary
+                .score_by_depth
+                .entry(record.depth / depth_bucket * depth_bucket)
+                .or_default()
+                .entry(record.score)
+                .or_default() += 1;
+            *expansions.entry(record.position).or_insert(0) += 1;
+        }
+
+        summary.num_positions = expansions.len();
+        for count in expansions.into_values() {
+            *summary.expansions_per_position.entry(count).or_default() += 1;
+        }
+        Ok(summary)
+    }
+}
+
+impl fmt::Display for TraceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} expansions of {} distinct positions, up to depth {}, {} neighbours generated, {} \
+             enqueued",
+            self.num_expansions, self.num_positions, self.max_depth, self.generated, self.enqueued
+        )?;
+
+        writeln!(f, "Scores by depth:")?;
+        for (&depth, scores) in &self.score_by_depth {
+            let count: usize = scores.values().sum();
+            let total: usize = scores
+                .iter()
+                .map(|(&score, &count)| score as usize * count)
+                .sum();
+            writeln!(
+                f,
+                "{:>6}..{:<6} {:>10} expansions, score {}..={}, mean {:.1}: {}",
+                depth,
+                depth.saturating_add(self.depth_bucket),
+                count,
+                scores.keys().next().unwrap_or(&0),
+                scores.keys().next_back().unwrap_or(&0),
+                total as f64 / count as f64,
+                scores
+                    .iter()
+                    .map(|(score, count)| format!("{}:{}", score, count))
+                    .join(" ")
+            )?;
+        }
+
+        writeln!(f, "Expansions per position:")?;
+        for (expansions, positions) in &self.expansions_per_position {
+            writeln!(f, "{:>6} {:>10} positions", expansions, positions)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let records = [
+            TraceRecord {
+                position: Position::solved(),
+                score: 25,
+                depth: 0,
+                index: 0,
+                parent: None,
+                generated: 300,
+                enqueued: 12,
+            },
+            TraceRecord {
+                position: Position::solved(),
+                score: 7,
+                depth: 51_237,
+                index: (1 << INDEX_BITS) - 2,
+                parent: Some(17),
+                generated: 1,
+                enqueued: 0,
+            },
+        ];
+        for record in records {
+            assert_eq!(TraceRecord::from_bytes(&record.to_bytes()), record);
+        }
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("trace");
+        let writer = TraceWriter::create(&path).unwrap();
+        writer.write(&records).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let file = File::open(&path).unwrap();
+        let read = TraceReader::new(file)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read, records);
+
+        let summary = TraceSummary::new(read.into_iter().map(Ok), 100).unwrap();
+        assert_eq!(summary.num_expansions, 2);
+        assert_eq!(summary.num_positions, 1);
+        assert_eq!(summary.max_depth, 51_237);
+        assert_eq!(summary.expansions_per_position[&2], 1);
+        assert_eq!(summary.score_by_depth[&51_200][&7], 1);
+
+        // A truncated record is an error, not the end of the trace
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(&records[0].to_bytes()[..10]);
+        let mut reader = TraceReader::new(&bytes[..]).unwrap();
+        assert!(reader.next().unwrap().is_err());
+        assert!(TraceReader::new(&b"SQ1"[..]).is_err());
+    }
+}
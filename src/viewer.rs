@@ -0,0 +1,387 @@
+//! The text formats of the 3D viewer in `web3d/`, to solve the states built in it.
+//!
+//! A state is written like `WRB,WB,WBO,WO|WOG,WG,WGR,WR true YO,YOB,YB,YBR|YR,YRG,YG,YGO`: the
+//! pieces of the top layer, whether the middle layer is solved, and the pieces of the bottom layer.
+//! Each layer has a `|` where it can be sliced, if it can. The movements are written like
+//! `RotateTop(3), Flip, RotateBottom(11)`, where a rotation moves the first pieces of the layer, of
+//! the given number of units, to its end.
+
+use crate::find_solution::{SearchMode, Solver};
+use crate::piece::Piece;
+use crate::position::Change;
+use crate::Position;
+use anyhow::{anyhow, bail, ensure, Context, Error, Result};
+use itertools::Itertools;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The state of the puzzle as the viewer represents it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ViewerState {
+    top: Vec<Piece>,
+    middle_solved: bool,
+    bottom: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ViewerMovement {
+    /// Move the first pieces of the top layer, of this number of units, to its end
+    RotateTop(u8),
+    /// Move the first pieces of the bottom layer, of this number of units, to its end
+    RotateBottom(u8),
+    /// Exchange the first 6 units of each layer, which also turns the middle layer
+    Flip,
+}
+
+/// The number of units in a layer, where a small piece has 1 unit and a big piece has 2
+const LAYER_UNITS: u8 = 12;
+
+impl ViewerState {
+    pub fn solved() -> Self {
+        ViewerState::from_position(Position::solved())
+    }
+
+    /// The state of a position, whose middle layer is always solved
+    pub fn from_position(position: Position) -> Self {
+        let pieces = position.pieces();
+        let num_top_pieces = num_pieces_in(&pieces, LAYER_UNITS).expect("the top layer is whole");
+        ViewerState {
+            top: pieces[..num_top_pieces].to_vec(),
+            middle_solved: true,
+            bottom: pieces[num_top_pieces..].to_vec(),
+        }
+    }
+
+    /// The position of this state, or `None` if a layer cannot be sliced. The orientation of the
+    /// middle layer is not part of positions.
+    pub fn position(&self) -> Option<Position> {
+        num_pieces_in(&self.top, LAYER_UNITS / 2)?;
+        num_pieces_in(&self.bottom, LAYER_UNITS / 2)?;
+
+        let pieces = self.top.iter().chain(&self.bottom).copied().collect_vec();
+        Some(Position::from_pieces(pieces.try_into().ok()?))
+    }
+
+    /// The pieces of the top layer, from the first one that a rotation moves to the end
+    pub fn top(&self) -> &[Piece] {
+        &self.top
+    }
+
+    pub fn bottom(&self) -> &[Piece] {
+        &self.bottom
+    }
+
+    pub fn is_middle_solved(&self) -> bool {
+        self.middle_solved
+    }
+
+    /// Perform the movement, or fail like the viewer if it's not possible
+    pub fn apply(&mut self, movement: ViewerMovement) -> Result<()> {
+        match movement {
+            ViewerMovement::RotateTop(units) => rotate(&mut self.top, units),
+            ViewerMovement::RotateBottom(units) => rotate(&mut self.bottom, units),
+            ViewerMovement::Flip => {
+                let num_top = num_pieces_in(&self.top, LAYER_UNITS / 2)
+                    .context("the top layer cannot be sliced")?;
+                let num_bottom = num_pieces_in(&self.bottom, LAYER_UNITS / 2)
+                    .context("the bottom layer cannot be sliced")?;
+
+                let flipped_top = self.top.drain(..num_top).collect_vec();
+                let flipped_bottom = self.bottom.drain(..num_bottom).collect_vec();
+                self.top.splice(..0, flipped_bottom);
+                self.bottom.splice(..0, flipped_top);
+                self.middle_solved = !self.middle_solved;
+                Ok(())
+            }
+        }
+    }
+
+    /// Find movements that solve this state, including the middle layer.
+    ///
+    /// The layers are first rotated so that they can be sliced, and flipped once if the middle
+    /// layer is turned. The resulting position is then solved by `solver`, and each of its
+    /// changes is translated to the movements of the viewer.
+    pub fn solve(&self, solver: &Solver) -> Result<Vec<ViewerMovement>> {
+        let mut state = self.clone();
+        let mut movements = vec![];
+
+        for is_top in [true, false] {
+            let layer = if is_top { &state.top } else { &state.bottom };
+            if num_pieces_in(layer, LAYER_UNITS / 2).is_some() {
+                continue;
+            }
+
+            let units = (1..LAYER_UNITS)
+                .filter(|&units| {
+                    let mut rotated = layer.clone();
+                    rotate(&mut rotated, units).is_ok()
+                        && num_pieces_in(&rotated, LAYER_UNITS / 2).is_some()
+                })
+                .min_by_key(|&units| units.min(LAYER_UNITS - units))
+                .context("a layer cannot be rotated so that it can be sliced")?;
+            movements.push(if is_top {
+                ViewerMovement::RotateTop(units)
+            } else {
+                ViewerMovement::RotateBottom(units)
+            });
+            state.apply(movements[movements.len() - 1])?;
+        }
+
+        if !state.middle_solved {
+            movements.push(ViewerMovement::Flip);
+            state.apply(ViewerMovement::Flip)?;
+        }
+
+        let position = state.position().expect("both layers can be sliced");
+        let solution = solver.solve(position)?.context("no solution was found")?;
+        for movement in &solution.movements()[1..] {
+            state.push_change(movement.change(), &mut movements)?;
+        }
+
+        ensure!(
+            state == ViewerState::solved(),
+            "the movements lead to {} instead of the solved state",
+            state
+        );
+        Ok(movements)
+    }
+
+    /// Perform a compound change with the movements of the viewer, adding them to `movements`
+    pub fn push_change(
+        &mut self,
+        change: Change,
+        movements: &mut Vec<ViewerMovement>,
+    ) -> Result<()> {
+        let [top_before, bottom_before, top_after, bottom_after] = change.rotations();
+        for (top, bottom) in [(top_before, bottom_before), (top_after, bottom_after)] {
+            // The change moves pieces from the end of the layer to its start, and the viewer moves
+            // the other ones from its start to its end
+            if top != 0 {
+                let units = self.top[..self.top.len() - top as usize]
+                    .iter()
+                    .map(|piece| piece.size())
+                    .sum();
+                movements.push(ViewerMovement::RotateTop(units));
+                self.apply(ViewerMovement::RotateTop(units))?;
+            }
+            if bottom != 0 {
+                let units = self.bottom[..self.bottom.len() - bottom as usize]
+                    .iter()
+                    .map(|piece| piece.size())
+                    .sum();
+                movements.push(ViewerMovement::RotateBottom(units));
+                self.apply(ViewerMovement::RotateBottom(units))?;
+            }
+
+            movements.push(ViewerMovement::Flip);
+            self.apply(ViewerMovement::Flip)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Solve a state written by the viewer, returning the movements in its format. The search runs
+/// on a single thread and returns the shortest solution found within `time_limit`.
+pub fn solve(state: &str, time_limit: Duration) -> Result<String> {
+    let state: ViewerState = state.parse()?;
+    let solver = Solver::builder()
+        .num_threads(1)
+        .mode(SearchMode::Anytime {
+            time_limit: Some(time_limit),
+        })
+        .build();
+
+    Ok(state.solve(&solver)?.iter().join(", "))
+}
+
+/// The number of pieces at the start of the layer that make up exactly `units`, if any
+fn num_pieces_in(layer: &[Piece], units: u8) -> Option<usize> {
+    let mut total = 0;
+    for (n, piece) in layer.iter().enumerate() {
+        if total == units {
+            return Some(n);
+        }
+        total += piece.size();
+    }
+    if total == units {
+        Some(layer.len())
+    } else {
+        None
+    }
+}
+
+fn rotate(layer: &mut [Piece], units: u8) -> Result<()> {
+    ensure!(
+        (1..LAYER_UNITS).contains(&units),
+        "invalid number of steps: {}",
+        units
+    );
+    let num_pieces = num_pieces_in(layer, units)
+        .with_context(|| format!("cannot find a clear cut after {} steps", units))?;
+    layer.rotate_left(num_pieces);
+    Ok(())
+}
+
+impl FromStr for ViewerState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (top, middle, bottom) = s
+            .split(' ')
+            .collect_tuple()
+            .context("expected the top layer, the middle layer and the bottom layer")?;
+
+        let mut remaining = Position::solved().pieces().to_vec();
+        let mut parse_layer = |layer: &str| -> Result<Vec<Piece>> {
+            let pieces = layer
+                .split([',', '|'])
+                .map(|name| {
+                    let piece = name.parse()?;
+                    let index = remaining
+                        .iter()
+                        .position(|&remaining| remaining == piece)
+                        .ok_or_else(|| anyhow!("the piece {} is used twice", name))?;
+                    Ok(remaining.swap_remove(index))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let units: u8 = pieces.iter().map(|piece| piece.size()).sum();
+            ensure!(
+                units == LAYER_UNITS,
+                "the layer {} has {} units instead of {}",
+                layer,
+                units,
+                LAYER_UNITS
+            );
+            Ok(pieces)
+        };
+
+        let top = parse_layer(top)?;
+        let bottom = parse_layer(bottom)?;
+        let middle_solved = match middle {
+            "true" => true,
+            "false" => false,
+            _ => bail!("invalid middle layer: {}", middle),
+        };
+
+        Ok(ViewerState {
+            top,
+            middle_solved,
+            bottom,
+        })
+    }
+}
+
+impl fmt::Display for ViewerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn write_layer(f: &mut fmt::Formatter, layer: &[Piece]) -> fmt::Result {
+            let mut units = 0;
+            for (n, piece) in layer.iter().enumerate() {
+                if n > 0 {
+                    f.write_str(if units == LAYER_UNITS / 2 { "|" } else { "," })?;
+                }
+                write!(f, "{}", piece)?;
+                units += piece.size();
+            }
+            Ok(())
+        }
+
+        write_layer(f, &self.top)?;
+        write!(f, " {} ", self.middle_solved)?;
+        write_layer(f, &self.bottom)
+    }
+}
+
+impl fmt::Display for ViewerMovement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ViewerMovement::RotateTop(units) => write!(f, "RotateTop({})", units),
+            ViewerMovement::RotateBottom(units) => write!(f, "RotateBottom({})", units),
+            ViewerMovement::Flip => f.write_str("Flip"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::NeighboursStack;
+
+    const SOLVED: &str = "WRB,WB,WBO,WO|WOG,WG,WGR,WR true YO,YOB,YB,YBR|YR,YRG,YG,YGO";
+
+    #[test]
+    fn format() {
+        let state: ViewerState = SOLVED.parse().unwrap();
+        assert_eq!(state, ViewerState::solved());
+        assert_eq!(state.to_string(), SOLVED);
+        assert_eq!(state.position(), Some(Position::solved()));
+
+        // Like the viewer, a layer without a cut after 6 units has no `|`
+        let mut rotated = state.clone();
+        for movement in [
+            ViewerMovement::RotateTop(3),
+            ViewerMovement::Flip,
+            ViewerMovement::RotateTop(1),
+        ] {
+            rotated.apply(movement).unwrap();
+        }
+        assert_eq!(
+            rotated.to_string(),
+            "YOB,YB,YBR,WGR,WR,WRB,WB,YO false WBO,WO,WOG,WG|YR,YRG,YG,YGO"
+        );
+        assert!(rotated.apply(ViewerMovement::Flip).is_err());
+        assert!(rotated.apply(ViewerMovement::RotateBottom(1)).is_err());
+        assert_eq!(rotated.position(), None);
+        assert_eq!(rotated.to_string().parse::<ViewerState>().unwrap(), rotated);
+
+        assert!("WRB,WB,WBO,WO|WOG,WG,WGR,WR true"
+            .parse::<ViewerState>()
+            .is_err());
+        assert!(SOLVED.replace("WRB", "WBR").parse::<ViewerState>().is_err());
+        assert!(SOLVED.replace("YGO", "WB").parse::<ViewerState>().is_err());
+        assert!(SOLVED
+            .replace("true", "yes")
+            .parse::<ViewerState>()
+            .is_err());
+    }
+
+    #[test]
+    fn same_changes_as_positions() {
+        let mut stack = NeighboursStack::new();
+        Position::solved().neighbours(&mut stack);
+
+        for movement in stack.neighbours() {
+            let mut state = ViewerState::solved();
+            let mut movements = vec![];
+            state
+                .push_change(movement.change(), &mut movements)
+                .unwrap();
+            assert_eq!(state, ViewerState::from_position(movement.position()));
+        }
+    }
+
+    #[test]
+    fn solve_built_states() {
+        let solver = Solver::builder().num_threads(1).build();
+
+        let mut state = ViewerState::solved();
+        for movement in [
+            ViewerMovement::RotateTop(3),
+            ViewerMovement::Flip,
+            ViewerMovement::RotateTop(1),
+            ViewerMovement::RotateBottom(2),
+        ] {
+            state.apply(movement).unwrap();
+
+            let mut solved = state.clone();
+            for movement in state.solve(&solver).unwrap() {
+                solved.apply(movement).unwrap();
+            }
+            assert_eq!(solved, ViewerState::solved());
+        }
+
+        assert_eq!(solve(SOLVED, Duration::from_secs(1)).unwrap(), "");
+    }
+}
@@ -0,0 +1,20 @@
+//! The entry points of the WebAssembly build, for the viewer in `web3d/`
+
+use crate::viewer;
+use std::time::Duration;
+use wasm_bindgen::prelude::*;
+
+/// Solve the state written by `Cube.toString()`, returning the movements for
+/// `Cube.applyMovementsFromStr()`. The search returns the shortest solution found within
+/// `time_limit` seconds.
+#[wasm_bindgen]
+pub fn solve(state: &str, time_limit: f64) -> Result<String, JsValue> {
+    if !(time_limit > 0.0 && time_limit.is_finite()) {
+        return Err(JsValue::from_str(
+            "the time limit must be a positive number of seconds",
+        ));
+    }
+
+    viewer::solve(state, Duration::from_secs_f64(time_limit))
+        .map_err(|error| JsValue::from_str(&format!("{:#}", error)))
+}
@@ -544,44 +544,112 @@ function animate() {
 
 animate();
 
-const solutionStr = 'RotateBottom(7), Flip, RotateBottom(11), Flip, RotateBottom(5), Flip, RotateBottom(7), Flip, ' +
-    'RotateBottom(2), Flip, RotateBottom(5), RotateTop(8), Flip, RotateTop(10), Flip, RotateBottom(3), Flip, ' +
-    'RotateTop(1), Flip, RotateTop(9), Flip, RotateBottom(3), Flip, RotateBottom(5), Flip, RotateBottom(9), Flip, ' +
-    'RotateTop(9), Flip, RotateBottom(3), Flip, RotateBottom(9), Flip, RotateBottom(6), RotateTop(4)';
+// The solver runs in a worker, since a search blocks its thread until it's over
+const solverWorker = new Worker(new URL('./solver.worker.js', import.meta.url));
+
+// The buttons that move the cube, disabled during a search so that the solution found is still
+// for the current state, and so that only one search runs at a time
+const movingButtons = [
+    'flip', 'rotateTop1', 'rotateTop2', 'rotateBottom1', 'rotateBottom2', 'reset', 'solve', 'solveStep',
+].map(id => document.getElementById(id));
+
+// Where a failed search is reported, since the buttons can't throw anywhere visible
+const status = document.getElementById('status');
+
+function showError(error) {
+    console.error(error);
+    status.textContent = `The search failed: ${error.message}`;
+}
+
+// Solve the current state, in the format of `Cube.applyMovementsFromStr()`. The search stops after
+// a few seconds with the shortest solution found
+function findSolution() {
+    status.textContent = '';
+    const wasDisabled = movingButtons.map(button => button.disabled);
+    for (const button of movingButtons) {
+        button.disabled = true;
+    }
+    document.body.style.cursor = 'progress';
+
+    const finish = () => {
+        movingButtons.forEach((button, i) => button.disabled = wasDisabled[i]);
+        document.body.style.cursor = '';
+    };
+    return new Promise((resolve, reject) => {
+        solverWorker.onmessage = event => {
+            finish();
+            if (event.data.error) {
+                reject(new Error(event.data.error));
+            } else {
+                resolve(event.data.solution);
+            }
+        };
+        solverWorker.onerror = event => {
+            finish();
+            reject(new Error(event.message));
+        };
+        solverWorker.postMessage({state: cube.toString(), timeLimit: 5});
+    });
+}
+
+// The remaining steps of the solution played by `solveStep`, which are found again once the cube
+// is moved by other means
+let nextSolutionSteps = [];
 
 document.getElementById('flip').onclick = () => {
+    nextSolutionSteps = [];
     cube.flip();
 };
 document.getElementById('rotateTop1').onclick = () => {
+    nextSolutionSteps = [];
     cube.rotateTop(1);
 };
 document.getElementById('rotateTop2').onclick = () => {
+    nextSolutionSteps = [];
     cube.rotateTop(2);
 };
 document.getElementById('rotateBottom1').onclick = () => {
+    nextSolutionSteps = [];
     cube.rotateBottom(1);
 };
 document.getElementById('rotateBottom2').onclick = () => {
+    nextSolutionSteps = [];
     cube.rotateBottom(2);
 };
 document.getElementById('reset').onclick = () => {
+    nextSolutionSteps = [];
     cube.setFromString('WRB,WB,WBO,WO|WOG,WG,WGR,WR true YO,YOB,YB,YBR|YR,YRG,YG,YGO');
 };
-document.getElementById('solve').onclick = () => {
-    cube.applyMovementsFromStr(solutionStr);
+document.getElementById('solve').onclick = async () => {
+    nextSolutionSteps = [];
+    try {
+        const solution = await findSolution();
+        if (solution) {
+            await cube.applyMovementsFromStr(solution);
+        }
+    } catch (error) {
+        showError(error);
+    }
 };
 
-const nextSolutionSteps = solutionStr.split(', ');
-
-document.getElementById('solveStep').onclick = event => {
+document.getElementById('solveStep').onclick = async event => {
     const button = event.currentTarget;
     button.disabled = true;
 
-    const nextStep = nextSolutionSteps.shift();
-    if (nextStep) {
-        cube.applyMovementsFromStr(nextStep).then(() => {
-            button.disabled = false;
-        });
+    try {
+        if (!nextSolutionSteps.length) {
+            const solution = await findSolution();
+            nextSolutionSteps = solution ? solution.split(', ') : [];
+        }
+
+        const nextStep = nextSolutionSteps.shift();
+        if (nextStep) {
+            await cube.applyMovementsFromStr(nextStep);
+        }
+    } catch (error) {
+        showError(error);
+    } finally {
+        button.disabled = false;
     }
 };
 document.getElementById('fast').onclick = () => {
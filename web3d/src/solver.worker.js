@@ -0,0 +1,13 @@
+// Runs the solver, built to WebAssembly in `pkg/` with `npm run build:wasm`, outside of the main
+// thread, so that the page keeps animating while it searches
+const solver = import('bachar-cube');
+
+onmessage = async event => {
+    const {state, timeLimit} = event.data;
+    try {
+        const {solve} = await solver;
+        postMessage({solution: solve(state, timeLimit)});
+    } catch (error) {
+        postMessage({error: String(error)});
+    }
+};
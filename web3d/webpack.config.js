@@ -6,5 +6,8 @@ module.exports = {
         filename: 'main.js',
         path: path.resolve(__dirname, 'dist'),
     },
-    mode: 'development'
+    mode: 'development',
+    experiments: {
+        asyncWebAssembly: true,
+    },
 };
\ No newline at end of file